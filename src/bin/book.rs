@@ -0,0 +1,87 @@
+extern crate minimax;
+
+use std::env;
+use std::fs;
+use std::process;
+
+use minimax::board::Size;
+use minimax::minimax::AI;
+use minimax::opening::{self, BookError};
+
+const USAGE: &str = "\
+Usage: book generate DEPTH [COLS ROWS]
+       book verify FILE [COLS ROWS]
+       book convert FILE OUTPUT [COLS ROWS]
+
+Generate writes a book of every position up to DEPTH moves to stdout.
+Verify re-solves every line of FILE and prints those that disagree.
+Convert writes the text book FILE to OUTPUT in the binary format.";
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(1)
+}
+
+fn parse_size(args: &[String]) -> Size {
+    match args.len() {
+        0 => Size::default(),
+        2 => {
+            let cols = args[0].parse().unwrap_or_else(|_| usage());
+            let rows = args[1].parse().unwrap_or_else(|_| usage());
+            Size::new(cols, rows).unwrap_or_else(|| usage())
+        }
+        _ => usage(),
+    }
+}
+
+fn read(path: &str) -> String {
+    fs::read_to_string(path).unwrap_or_else(|err| {
+        eprintln!("Could not read {}: {}", path, err);
+        process::exit(1)
+    })
+}
+
+/// Exits with `err` if a book could not be read.
+fn checked<T>(path: &str, result: Result<T, BookError>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("Invalid book {}: {}", path, err);
+        process::exit(1)
+    })
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if args.len() < 2 { usage() }
+
+    if args[0] == "convert" {
+        if args.len() < 3 { usage() }
+        let bytes = checked(&args[1], opening::to_binary(&read(&args[1]), parse_size(&args[3..])));
+        if let Err(err) = fs::write(&args[2], bytes) {
+            eprintln!("Could not write {}: {}", args[2], err);
+            process::exit(1)
+        }
+        return
+    }
+
+    let mut ai = AI::with_size(parse_size(&args[2..]));
+    match args[0].as_str() {
+        "generate" => {
+            let depth = args[1].parse().unwrap_or_else(|_| usage());
+            let book = opening::generate(&mut ai, depth).unwrap_or_else(|| {
+                eprintln!("Books are limited to boards of at most 9 columns");
+                process::exit(1)
+            });
+            for (moves, score) in book {
+                println!("{} {}", moves, score);
+            }
+        }
+        "verify" => {
+            let wrong = checked(&args[1], opening::verify(&mut ai, &read(&args[1])));
+            for &(ref moves, expected, score) in &wrong {
+                println!("{} {} (solved {})", moves, expected, score);
+            }
+            if !wrong.is_empty() { process::exit(1) }
+        }
+        _ => usage(),
+    }
+}
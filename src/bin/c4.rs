@@ -0,0 +1,236 @@
+extern crate minimax;
+
+use std::env;
+use std::fs;
+use std::process;
+use std::time::{Duration, Instant};
+
+use minimax::board::*;
+use minimax::engine::*;
+use minimax::minimax::{AI, Difficulty, Evaluation};
+
+const USAGE: &str = "\
+Usage: c4 play [--white PLAYER] [--black PLAYER] [--from MOVES] [--time SECS]
+               [--clock SECS[+INC]] [--save FILE] [COLS ROWS]
+       c4 solve MOVES [COLS ROWS]
+       c4 analyze MOVES [COLS ROWS]
+       c4 bench FILE [--threads N]
+
+Play runs a game in the terminal. PLAYER is human, perfect, random, greedy,
+depth:N or mistakes:P, and defaults to human for White and perfect for
+Black. Humans enter a column from 0, or undo, redo, restart, resign or draw.
+--time limits every move, --clock the whole game with an optional increment,
+and --save appends the finished game to FILE.
+
+Solve prints the exact score of the position after MOVES, columns numbered
+from 1, with the best move and the expected line of play.
+
+Analyze prints the exact score of playing each column.
+
+Bench weakly solves every line of FILE, made of a move string and its score,
+and prints how many were right and how long they took.";
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(1)
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
+/// Splits arguments into flags with their values and positional arguments.
+fn split(args: &[String]) -> (Vec<(&str, &str)>, Vec<&str>) {
+    let (mut flags, mut positional) = (Vec::new(), Vec::new());
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            positional.push(arg.as_str());
+        } else {
+            flags.push((arg.as_str(), args.next().unwrap_or_else(|| usage()).as_str()));
+        }
+    }
+    (flags, positional)
+}
+
+fn parse_size(args: &[&str]) -> Size {
+    match args.len() {
+        0 => Size::default(),
+        2 => {
+            let cols = args[0].parse().unwrap_or_else(|_| usage());
+            let rows = args[1].parse().unwrap_or_else(|_| usage());
+            Size::new(cols, rows).unwrap_or_else(|| usage())
+        }
+        _ => usage(),
+    }
+}
+
+fn parse_board(size: Size, moves: &str) -> Board {
+    Board::parse(size, moves).unwrap_or_else(|err| fail(format!("Invalid moves {:?}: {}", moves, err)))
+}
+
+fn parse_seconds(seconds: &str) -> Duration {
+    let seconds = seconds.parse::<f64>().unwrap_or_else(|_| usage());
+    if !seconds.is_finite() || seconds <= 0.0 { usage() }
+    Duration::from_millis((seconds * 1000.0) as u64)
+}
+
+fn parse_player(size: Size, spec: &str) -> (String, Box<dyn Player>) {
+    let mut parts = spec.splitn(2, ':');
+    let difficulty = match (parts.next().unwrap(), parts.next()) {
+        ("human", None) => return ("Human".to_string(), Box::new(Human)),
+        ("perfect", None) => Difficulty::Perfect,
+        ("random", None) => Difficulty::Random,
+        ("greedy", None) => Difficulty::Greedy,
+        ("depth", Some(depth)) => Difficulty::Depth(depth.parse().unwrap_or_else(|_| usage())),
+        ("mistakes", Some(p)) => Difficulty::Mistakes(p.parse().unwrap_or_else(|_| usage())),
+        _ => usage(),
+    };
+    let mut ai = AI::with_size(size);
+    ai.set_difficulty(difficulty);
+    (format!("Computer ({})", spec), Box::new(ai))
+}
+
+fn check_ongoing(board: &Board) {
+    if board.was_won().is_some() || board.moves == board.size().cells() {
+        fail("The game is already over.".to_string())
+    }
+}
+
+fn to_moves(line: &[u8]) -> String {
+    line.iter().map(|col| (col + 1).to_string()).collect::<Vec<_>>().join(" ")
+}
+
+fn play(args: &[String]) {
+    let (flags, positional) = split(args);
+    let size = parse_size(&positional);
+    let mut builder = EngineBuilder::new().size(size);
+    let (mut white, mut black) = ("human", "perfect");
+    let mut save = None;
+
+    for &(flag, value) in &flags {
+        match flag {
+            "--white" => white = value,
+            "--black" => black = value,
+            "--from" => builder = builder.position(parse_board(size, value)),
+            "--time" => builder = builder.time_control(TimeControl::PerMove(parse_seconds(value))),
+            "--clock" => {
+                let mut parts = value.splitn(2, '+');
+                let base = parse_seconds(parts.next().unwrap());
+                let control = match parts.next() {
+                    Some(increment) => TimeControl::Increment { base, increment: parse_seconds(increment) },
+                    None => TimeControl::SuddenDeath(base),
+                };
+                builder = builder.time_control(control);
+            }
+            "--save" => save = Some(value),
+            _ => usage(),
+        }
+    }
+
+    let (white_name, white) = parse_player(size, white);
+    let (black_name, black) = parse_player(size, black);
+    let summary = builder
+        .player(WHITE, &white_name, white)
+        .player(BLACK, &black_name, black)
+        .build()
+        .run();
+
+    if let Some(path) = save {
+        if let Err(err) = summary.record.append(path) {
+            fail(format!("Could not save game to {}: {}", path, err))
+        }
+    }
+}
+
+fn solve(args: &[String]) {
+    let (flags, positional) = split(args);
+    if !flags.is_empty() || positional.is_empty() { usage() }
+    let size = parse_size(&positional[1..]);
+    let mut board = parse_board(size, positional[0]);
+    check_ongoing(&board);
+
+    let mut ai = AI::with_size(size);
+    let score = ai.score(&mut board);
+    let line = ai.principal_variation(&board);
+    println!("Score: {}", score);
+    println!("Best move: {}", line[0] + 1);
+    println!("Line: {}", to_moves(&line));
+}
+
+fn analyze(args: &[String]) {
+    let (flags, positional) = split(args);
+    if !flags.is_empty() || positional.is_empty() { usage() }
+    let size = parse_size(&positional[1..]);
+    let board = parse_board(size, positional[0]);
+    check_ongoing(&board);
+
+    let mut ai = AI::with_size(size);
+    for (col, evaluation) in ai.analyze(&board).into_iter().enumerate() {
+        match evaluation {
+            Evaluation::Illegal => println!("{}: full", col + 1),
+            Evaluation::Pruned(score) => println!("{}: {} (loses at once)", col + 1, score),
+            Evaluation::Score(score) => println!("{}: {}", col + 1, score),
+        }
+    }
+}
+
+fn bench(args: &[String]) {
+    let (flags, positional) = split(args);
+    if positional.len() != 1 { usage() }
+    let path = positional[0];
+    let mut ai = AI::new();
+
+    for &(flag, value) in &flags {
+        match flag {
+            "--threads" => ai.set_threads(value.parse().unwrap_or_else(|_| usage())),
+            _ => usage(),
+        }
+    }
+
+    let text = fs::read_to_string(path).unwrap_or_else(|err| fail(format!("Could not read {}: {}", path, err)));
+    let (mut total, mut correct) = (0, 0);
+    let mut times = Vec::new();
+
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let mut parts = line.split_whitespace();
+        let mut board = parse_board(Size::default(), parts.next().unwrap());
+        let expected = parts.next()
+            .and_then(|score| score.parse::<i8>().ok())
+            .unwrap_or_else(|| fail(format!("Invalid line {:?}", line)));
+
+        let start = Instant::now();
+        let score = ai.negamax(&mut board, -1, 1);
+        times.push(start.elapsed());
+
+        if score == expected.signum() { correct += 1; }
+        total += 1;
+    }
+    if total == 0 { fail(format!("No positions in {}", path)) }
+
+    let seconds = times.iter()
+        .map(|time| time.as_secs() as f64 + time.subsec_nanos() as f64 / 1e9)
+        .collect::<Vec<_>>();
+    let sum = seconds.iter().sum::<f64>();
+    let max = seconds.iter().cloned().fold(0.0, f64::max);
+    println!("Statistics for {}", path);
+    println!("Correctness: {}/{}", correct, total);
+    println!("Total search time: {:.3}s", sum);
+    println!("Mean search time: {:.6}s", sum / total as f64);
+    println!("Max search time: {:.6}s", max);
+    if correct < total { process::exit(1) }
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if args.is_empty() { usage() }
+
+    match args[0].as_str() {
+        "play" => play(&args[1..]),
+        "solve" => solve(&args[1..]),
+        "analyze" => analyze(&args[1..]),
+        "bench" => bench(&args[1..]),
+        _ => usage(),
+    }
+}
@@ -1,105 +1,482 @@
+use std::error::Error;
 use std::fmt;
+use std::ops::{Add, BitAnd, BitOr, BitXor, Not, Shl, Shr, Sub};
+use std::str::FromStr;
+use fnv::FnvHashSet;
 
 pub const ROWS: u8 = 6;
 pub const COLS: u8 = 7;
 pub const WHITE: i8 = 0b0;
 pub const BLACK: i8 = 0b1;
 
-const BOARD_MASK: u64 = 0b0111111_0111111_0111111_0111111_0111111_0111111_0111111;
-const BOTTOM_ROW: u64 = 0b0000001_0000001_0000001_0000001_0000001_0000001_0000001;
-
-const TOP_MASK: [u64; 7] = [
-    0b0000000_0000000_0000000_0000000_0000000_0000000_0100000,
-    0b0000000_0000000_0000000_0000000_0000000_0100000_0000000,
-    0b0000000_0000000_0000000_0000000_0100000_0000000_0000000,
-    0b0000000_0000000_0000000_0100000_0000000_0000000_0000000,
-    0b0000000_0000000_0100000_0000000_0000000_0000000_0000000,
-    0b0000000_0100000_0000000_0000000_0000000_0000000_0000000,
-    0b0100000_0000000_0000000_0000000_0000000_0000000_0000000,
-];
-
-const BOT_MASK: [u64; 7] = [
-    0b0000000_0000000_0000000_0000000_0000000_0000000_0000001,
-    0b0000000_0000000_0000000_0000000_0000000_0000001_0000000,
-    0b0000000_0000000_0000000_0000000_0000001_0000000_0000000,
-    0b0000000_0000000_0000000_0000001_0000000_0000000_0000000,
-    0b0000000_0000000_0000001_0000000_0000000_0000000_0000000,
-    0b0000000_0000001_0000000_0000000_0000000_0000000_0000000,
-    0b0000001_0000000_0000000_0000000_0000000_0000000_0000000,
-];
-
-const COL_MASK: [u64; 7] = [
-    0b0000000_0000000_0000000_0000000_0000000_0000000_0111111,
-    0b0000000_0000000_0000000_0000000_0000000_0111111_0000000,
-    0b0000000_0000000_0000000_0000000_0111111_0000000_0000000,
-    0b0000000_0000000_0000000_0111111_0000000_0000000_0000000,
-    0b0000000_0000000_0111111_0000000_0000000_0000000_0000000,
-    0b0000000_0111111_0000000_0000000_0000000_0000000_0000000,
-    0b0111111_0000000_0000000_0000000_0000000_0000000_0000000,
-];
-
-const MOVE_ORDER: [u8; 7] = [3, 2, 4, 1, 5, 0, 6];
-
-#[derive(Clone, Eq, PartialEq, Hash)]
+/// Dimensions of a board. Each column is stored in `rows + 1` bits of a
+/// `u128`, so `cols * (rows + 1)` can be at most 128.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Size {
+    cols: u8,
+    rows: u8,
+    bottom_row: u128,
+    board_mask: u128,
+}
+
+impl Size {
+    pub fn new(cols: u8, rows: u8) -> Option<Self> {
+        if cols == 0 || rows == 0 || cols as u32 * (rows as u32 + 1) > 128 { return None }
+        let mut size = Size { cols, rows, bottom_row: 0, board_mask: 0 };
+        for col in 0..cols {
+            size.bottom_row |= size.bot_mask(col);
+            size.board_mask |= size.col_mask(col);
+        }
+        Some(size)
+    }
+
+    pub fn cols(&self) -> u8 { self.cols }
+
+    pub fn rows(&self) -> u8 { self.rows }
+
+    pub fn cells(&self) -> i8 { (self.cols * self.rows) as i8 }
+
+    /// Width of a position's key.
+    pub fn bits(&self) -> u32 { self.cols as u32 * self.height() as u32 }
+
+    fn height(&self) -> u8 { self.rows + 1 }
+
+    /// Whether keys fit in 64 bits.
+    pub(crate) fn narrow(&self) -> bool { self.bits() <= 64 }
+
+    fn top_mask(&self, col: u8) -> u128 {
+        1 << (self.rows - 1 + col * self.height())
+    }
+
+    fn bot_mask(&self, col: u8) -> u128 {
+        1 << (col * self.height())
+    }
+
+    fn col_mask(&self, col: u8) -> u128 {
+        ((1 << self.rows) - 1) << (col * self.height())
+    }
+
+    /// Columns from the center outwards.
+    fn move_order(&self) -> impl Iterator<Item = u8> {
+        let cols = self.cols as i8;
+        (0..cols).map(move |i| (cols / 2 + (1 - 2 * (i % 2)) * (i + 1) / 2) as u8)
+    }
+}
+
+impl Default for Size {
+    fn default() -> Self { Size::new(COLS, ROWS).unwrap() }
+}
+
+/// Why a move was rejected by `Board::play` or `Board::take_back`. Columns
+/// are numbered from 0, as in `Board::make_move`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MoveError {
+    /// A column that the board does not have.
+    OutOfRange(u8),
+    /// A column that is already full.
+    ColumnFull(u8),
+    /// A move after the game was won.
+    GameOver,
+    /// A column whose top stone was not played by the last mover, or which
+    /// is empty.
+    NotTakeBack(u8),
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MoveError::OutOfRange(col) => write!(f, "column {} out of range", col),
+            MoveError::ColumnFull(col) => write!(f, "column {} is full", col),
+            MoveError::GameOver => write!(f, "move after the game was won"),
+            MoveError::NotTakeBack(col) => write!(f, "cannot take back column {}", col),
+        }
+    }
+}
+
+impl Error for MoveError {}
+
+/// What a legal move did to the game.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MoveOutcome {
+    /// The game goes on.
+    Ongoing,
+    /// The player who moved won.
+    Won,
+    /// The board filled up without a winner.
+    Drawn,
+}
+
+/// Why a move string could not be parsed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ParseErrorKind {
+    /// Not a column digit.
+    InvalidChar(char),
+    /// A column, numbered from 1, that the board does not have.
+    OutOfRange(u8),
+    /// A column, numbered from 1, that is already full.
+    ColumnFull(u8),
+    /// A move after the game was won.
+    GameOver,
+}
+
+/// Error from `Board::parse`, with the index of the offending move.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ParseError {
+    pub index: usize,
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ParseErrorKind::InvalidChar(c) => write!(f, "invalid character {:?}", c)?,
+            ParseErrorKind::OutOfRange(col) => write!(f, "column {} out of range", col)?,
+            ParseErrorKind::ColumnFull(col) => write!(f, "column {} is full", col)?,
+            ParseErrorKind::GameOver => write!(f, "move after the game was won")?,
+        };
+        write!(f, " at move {}", self.index)
+    }
+}
+
+impl Error for ParseError {}
+
+/// Why a grid could not be read by `Board::from_grid` or `Board::from_fen`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GridError {
+    /// Not a stone, an empty cell or, in the one-line form, a run length.
+    InvalidChar(char),
+    /// Rows of different widths.
+    Ragged,
+    /// Dimensions that `Size::new` rejects.
+    Size(u8, u8),
+    /// A stone above an empty cell, by row from the bottom and column.
+    Floating(u8, u8),
+    /// Black has more stones than White, or White more than one extra.
+    Count,
+    /// Both players have four in a row, or the winner is not the last mover.
+    Won,
+}
+
+impl fmt::Display for GridError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GridError::InvalidChar(c) => write!(f, "invalid character {:?}", c),
+            GridError::Ragged => write!(f, "rows have different widths"),
+            GridError::Size(cols, rows) => write!(f, "unsupported size {}x{}", cols, rows),
+            GridError::Floating(row, col) => write!(f, "floating stone at row {}, column {}", row, col),
+            GridError::Count => write!(f, "impossible number of stones"),
+            GridError::Won => write!(f, "impossible win"),
+        }
+    }
+}
+
+impl Error for GridError {}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct Board {
     pub moves: i8,
-    pub owned: u64,
-    pub all: u64,
+    pub owned: u128,
+    pub all: u128,
+    size: Size,
 }
 
 impl Board {
-    pub fn new() -> Self { Board { moves: 0, owned: 0, all: 0 } }
+    pub fn new() -> Self { Self::with_size(Size::default()) }
 
+    pub fn with_size(size: Size) -> Self { Board { moves: 0, owned: 0, all: 0, size } }
+
+    pub fn size(&self) -> Size { self.size }
+
+    /// Replays a move string on the standard board, panicking if it is
+    /// invalid. See `Board::parse`.
     pub fn from(moves: &str) -> Self {
-        let mut board = Board::new();
-        for c in moves.chars() {
-            let c = (char::to_digit(c, 10).unwrap() - 1) as u8;
-            board.make_move(c);
+        Self::parse(Size::default(), moves).unwrap_or_else(|err| {
+            panic!("invalid move string {:?}: {}", moves, err)
+        })
+    }
+
+    /// Replays a move string of column digits, numbered from 1.
+    pub fn parse(size: Size, moves: &str) -> Result<Self, ParseError> {
+        let mut board = Board::with_size(size);
+        for (index, c) in moves.chars().enumerate() {
+            let error = |kind| Err(ParseError { index, kind });
+            let col = match char::to_digit(c, 10) {
+                Some(col) => col as u8,
+                None => return error(ParseErrorKind::InvalidChar(c)),
+            };
+            if col == 0 { return error(ParseErrorKind::OutOfRange(col)) }
+            match board.play(col - 1) {
+                Ok(_) => (),
+                Err(MoveError::OutOfRange(_)) => return error(ParseErrorKind::OutOfRange(col)),
+                Err(MoveError::ColumnFull(_)) => return error(ParseErrorKind::ColumnFull(col)),
+                Err(_) => return error(ParseErrorKind::GameOver),
+            }
+        }
+        Ok(board)
+    }
+
+    /// A move string that `Board::parse` replays into this position, if the
+    /// position can be reached in a legal game. Columns past the ninth have
+    /// no digit.
+    pub fn to_move_string(&self) -> Option<String> {
+        if self.size.cols > 9 { return None }
+        let mut board = self.clone();
+        let mut moves = Vec::with_capacity(self.moves as usize);
+        if !board.rewind(&mut moves, &mut FnvHashSet::default()) { return None }
+        Some(moves.iter().rev().map(|&col| char::from_digit(col as u32 + 1, 10).unwrap()).collect())
+    }
+
+    /// Takes back stones of alternating colors until the board is empty,
+    /// never passing through a position where the game was already won.
+    /// Positions that cannot be emptied are kept in `dead`, so that each is
+    /// searched at most once.
+    fn rewind(&mut self, moves: &mut Vec<u8>, dead: &mut FnvHashSet<u128>) -> bool {
+        if self.moves == 0 { return self.all == 0 }
+        if dead.contains(&self.key()) { return false }
+        for col in 0..self.size.cols {
+            if self.take_back(col).is_err() { continue }
+            if self.was_won().is_none() {
+                moves.push(col);
+                if self.rewind(moves, dead) { return true }
+                moves.pop();
+            }
+            self.make_move(col);
+        }
+        dead.insert(self.key());
+        false
+    }
+
+    /// Reads a grid as printed by `impl Display for Board`: one line per row
+    /// from the top, with `W`, `B` or `.` for each cell. Whitespace and lines
+    /// of column indices are ignored. The size is taken from the grid and the
+    /// side to move from the number of stones.
+    pub fn from_grid(grid: &str) -> Result<Self, GridError> {
+        let mut rows = Vec::new();
+        for line in grid.lines() {
+            if line.chars().all(|c| c.is_whitespace() || c.is_ascii_digit()) { continue }
+            let mut row = Vec::new();
+            for c in line.chars().filter(|c| !c.is_whitespace()) {
+                row.push(match c {
+                    'W' => Some(WHITE),
+                    'B' => Some(BLACK),
+                    '.' => None,
+                    _ => return Err(GridError::InvalidChar(c)),
+                });
+            }
+            rows.push(row);
+        }
+        Self::from_rows(rows)
+    }
+
+    /// The grid read by `Board::from_grid`, without column indices.
+    pub fn to_grid(&self) -> String {
+        let mut grid = String::new();
+        for row in (0..self.size.rows).rev() {
+            let line = (0..self.size.cols).map(|col| match self.get(row, col) {
+                None => ".",
+                Some(WHITE) => "W",
+                Some(_) => "B",
+            });
+            grid.push_str(&line.collect::<Vec<_>>().join(" "));
+            grid.push('\n');
+        }
+        grid
+    }
+
+    /// Reads the one-line form written by `Board::to_fen`: rows from the top
+    /// separated by `/`, with `W` and `B` for stones and numbers for runs of
+    /// empty cells, such as `7/7/7/7/7/3W3` after a move in the center.
+    pub fn from_fen(fen: &str) -> Result<Self, GridError> {
+        let mut rows = Vec::new();
+        for part in fen.trim().split('/') {
+            let mut row = Vec::new();
+            let mut run = 0;
+            for c in part.chars() {
+                if let Some(digit) = c.to_digit(10) {
+                    run = run * 10 + digit as usize;
+                    if run > 128 { return Err(GridError::InvalidChar(c)) }
+                    continue
+                }
+                row.extend((0..run).map(|_| None));
+                run = 0;
+                row.push(match c {
+                    'W' => Some(WHITE),
+                    'B' => Some(BLACK),
+                    _ => return Err(GridError::InvalidChar(c)),
+                });
+            }
+            row.extend((0..run).map(|_| None));
+            rows.push(row);
+        }
+        Self::from_rows(rows)
+    }
+
+    /// The one-line form read by `Board::from_fen`.
+    pub fn to_fen(&self) -> String {
+        let mut rows = Vec::new();
+        for row in (0..self.size.rows).rev() {
+            let mut line = String::new();
+            let mut run = 0;
+            for col in 0..self.size.cols {
+                let stone = match self.get(row, col) {
+                    None => { run += 1; continue }
+                    Some(WHITE) => 'W',
+                    Some(_) => 'B',
+                };
+                if run > 0 { line.push_str(&run.to_string()); run = 0; }
+                line.push(stone);
+            }
+            if run > 0 { line.push_str(&run.to_string()); }
+            rows.push(line);
+        }
+        rows.join("/")
+    }
+
+    /// Builds a board from cells listed by row from the top.
+    fn from_rows(rows: Vec<Vec<Option<i8>>>) -> Result<Self, GridError> {
+        let cols = rows.first().map_or(0, |row| row.len());
+        if rows.iter().any(|row| row.len() != cols) { return Err(GridError::Ragged) }
+        if cols > 128 || rows.len() > 128 { return Err(GridError::Size(cols as u8, rows.len() as u8)) }
+        let size = Size::new(cols as u8, rows.len() as u8)
+            .ok_or(GridError::Size(cols as u8, rows.len() as u8))?;
+
+        let (mut white, mut black) = (0u128, 0u128);
+        for (row, cells) in rows.iter().rev().enumerate() {
+            for (col, &cell) in cells.iter().enumerate() {
+                let (row, col) = (row as u8, col as u8);
+                let below = row > 0 && rows[rows.len() - row as usize][col as usize].is_none();
+                if cell.is_some() && below { return Err(GridError::Floating(row, col)) }
+                let mask = 1 << (col * size.height() + row);
+                match cell {
+                    Some(WHITE) => white |= mask,
+                    Some(_) => black |= mask,
+                    None => (),
+                }
+            }
+        }
+
+        let (whites, blacks) = (white.count_ones() as i8, black.count_ones() as i8);
+        if whites != blacks && whites != blacks + 1 { return Err(GridError::Count) }
+
+        let moves = whites + blacks;
+        let board = Board {
+            moves,
+            owned: if moves & 1 == 0 { white } else { black },
+            all: white | black,
+            size,
+        };
+        match (board.connected(white), board.connected(black)) {
+            (true, true) => return Err(GridError::Won),
+            (true, false) if moves & 1 == 0 => return Err(GridError::Won),
+            (false, true) if moves & 1 == 1 => return Err(GridError::Won),
+            _ => (),
         }
-        board
+        Ok(board)
     }
 
     pub fn reset(&mut self) { self.moves = 0; self.owned = 0; self.all = 0; }
 
     pub fn valid_moves(&self) -> Vec<u8> {
-        MOVE_ORDER.iter().filter(|&&col| {
-            self.all & TOP_MASK[col as usize] == 0
-        }).cloned().collect()
+        self.size.move_order().filter(|&col| {
+            self.all & self.size.top_mask(col) == 0
+        }).collect()
     }
 
     pub fn safe_moves(&self) -> Vec<u8> {
-        let safe = self.safe();
-        let mut moves = MOVE_ORDER.iter().filter(|&&col| {
-            self.all & TOP_MASK[col as usize] == 0
-        }).filter(|&&col| {
-            safe & COL_MASK[col as usize] != 0
-        }).cloned().collect::<Vec<_>>();
-
-        moves.sort_by_key(|&col| {
-            let moved = safe & COL_MASK[col as usize];
-            self.score_move(moved)
-        });
-        moves
+        // Most boards fit in 64 bits, where the search spends most of its time
+        if self.size.narrow() {
+            safe_moves::<u64>(self)
+        } else {
+            safe_moves::<u128>(self)
+        }
     }
 
-    pub fn key(&self) -> u64 {
+    pub fn key(&self) -> u128 {
         self.all + self.owned
     }
 
+    /// The position reflected left to right, which has the same score.
+    pub fn mirror(&self) -> Board {
+        Board {
+            moves: self.moves,
+            owned: self.mirror_bits(self.owned),
+            all: self.mirror_bits(self.all),
+            size: self.size,
+        }
+    }
+
+    /// Key shared by a position and its mirror image: the smaller of the two.
+    pub fn canonical_key(&self) -> u128 {
+        let key = self.key();
+        key.min(self.mirror_bits(key))
+    }
+
+    fn mirror_bits(&self, bits: u128) -> u128 {
+        let (cols, h) = (self.size.cols, self.size.height());
+        // A whole column of height `h`, which may fill all 128 bits
+        let column = (self.size.col_mask(0) << 1) | 1;
+        if self.size.narrow() {
+            mirror(bits as u64, column as u64, cols, h) as u128
+        } else {
+            mirror(bits, column, cols, h)
+        }
+    }
+
+    /// Plays in `col` after checking that the move is legal.
+    pub fn play(&mut self, col: u8) -> Result<MoveOutcome, MoveError> {
+        if col >= self.size.cols { return Err(MoveError::OutOfRange(col)) }
+        if self.was_won().is_some() { return Err(MoveError::GameOver) }
+        if self.all & self.size.top_mask(col) != 0 { return Err(MoveError::ColumnFull(col)) }
+
+        self.make_move(col);
+        if self.was_won().is_some() {
+            Ok(MoveOutcome::Won)
+        } else if self.moves == self.size.cells() {
+            Ok(MoveOutcome::Drawn)
+        } else {
+            Ok(MoveOutcome::Ongoing)
+        }
+    }
+
+    /// Takes back the last stone in `col` after checking that it was played
+    /// by the last mover.
+    pub fn take_back(&mut self, col: u8) -> Result<(), MoveError> {
+        if col >= self.size.cols { return Err(MoveError::OutOfRange(col)) }
+        let column = self.all & self.size.col_mask(col);
+        let top = (column + self.size.bot_mask(col)) >> 1;
+        if column == 0 || (self.all ^ self.owned) & top == 0 { return Err(MoveError::NotTakeBack(col)) }
+        self.undo_move(col);
+        Ok(())
+    }
+
+    /// Plays in `col` without any checks. Playing in a full column corrupts
+    /// the board.
     pub fn make_move(&mut self, col: u8) {
+        let shift = col as u32 * self.size.height() as u32;
         self.owned ^= self.all;
-        self.all |= self.all + BOT_MASK[col as usize];
+        self.all = if self.size.narrow() {
+            add_stone(self.all as u64, shift) as u128
+        } else {
+            add_stone(self.all, shift)
+        };
         self.moves += 1;
     }
 
+    /// Takes back the last stone in `col` without any checks.
     pub fn undo_move(&mut self, col: u8) {
+        let shift = col as u32 * self.size.height() as u32;
         self.moves -= 1;
-        self.all ^= ((self.all & COL_MASK[col as usize]) + BOT_MASK[col as usize]) >> 1;
+        self.all = if self.size.narrow() {
+            remove_stone(self.all as u64, self.size.rows, shift) as u128
+        } else {
+            remove_stone(self.all, self.size.rows, shift)
+        };
         self.owned ^= self.all;
     }
 
     pub fn will_win(&self, col: u8) -> bool {
-        (self.win_positions() & self.possible() & COL_MASK[col as usize]) != 0
+        (self.win_positions() & self.possible() & self.size.col_mask(col)) != 0
     }
 
     pub fn was_won(&self) -> Option<i8> {
@@ -109,93 +486,50 @@ impl Board {
             (self.owned ^ self.all, self.owned)
         };
 
-        let l = white & (white >> 6);
-        if l & (l >> 12) != 0 { return Some(WHITE) }
-
-        let r = white & (white >> 8);
-        if r & (r >> 16) != 0 { return Some(WHITE) }
-
-        let h = white & (white >> 7);
-        if h & (h >> 14) != 0 { return Some(WHITE) }
-
-        let v = white & (white >> 1);
-        if v & (v >> 2) != 0 { return Some(WHITE) }
-
-        let l = black & (black >> 6);
-        if l & (l >> 12) != 0 { return Some(BLACK) }
-
-        let r = black & (black >> 8);
-        if r & (r >> 16) != 0 { return Some(BLACK) }
-
-        let h = black & (black >> 7);
-        if h & (h >> 14) != 0 { return Some(BLACK) }
-
-        let v = black & (black >> 1);
-        if v & (v >> 2) != 0 { return Some(BLACK) }
-
+        if self.connected(white) { return Some(WHITE) }
+        if self.connected(black) { return Some(BLACK) }
         None
     }
 
-    fn score_move(&self, moved: u64) -> i8 {
-        -(Self::get_winning_positions(self.owned | moved, self.all | moved).count_ones() as i8)
-    }
-
-    fn safe(&self) -> u64 {
-        let mut possible = self.possible();
-        let opponent = self.opponent_win();
-        let forced = possible & opponent;
-
-        if forced != 0 {
-            if forced & (forced - 1) != 0 {
-                return 0
-            } else {
-                possible = forced;
-            }
+    fn connected(&self, stones: u128) -> bool {
+        let h = self.size.rows as u32;
+        for &shift in &[h, h + 2, h + 1, 1] {
+            let pairs = stones & stones.down(shift);
+            if pairs & pairs.down(2 * shift) != 0 { return true }
         }
-        possible & !(opponent >> 1)
+        false
     }
 
-    fn win_positions(&self) -> u64 {
-        Self::get_winning_positions(self.owned, self.all)
+    /// Static evaluation for the side to move: the number of empty cells that
+    /// would complete four in a row for it, minus those for the opponent.
+    pub fn heuristic(&self) -> i32 {
+        self.win_positions().count_ones() as i32 - self.opponent_win().count_ones() as i32
     }
 
-    fn opponent_win(&self) -> u64 {
-        Self::get_winning_positions(self.owned ^ self.all, self.all)
+    fn win_positions(&self) -> u128 {
+        self.get_winning_positions(self.owned, self.all)
     }
 
-    fn possible(&self) -> u64 {
-        (self.all + BOTTOM_ROW) & BOARD_MASK
+    fn opponent_win(&self) -> u128 {
+        self.get_winning_positions(self.owned ^ self.all, self.all)
     }
 
-    fn get_winning_positions(owned: u64, all: u64) -> u64 {
-        let mut w = (owned << 1) & (owned << 2) & (owned << 3);
-
-        let mut h = (owned << 7) & (owned << 14);
-        w |= h & (owned << 21);
-        w |= h & (owned >> 7);
-        h >>= 21;
-        w |= h & (owned << 7);
-        w |= h & (owned >> 21);
-
-        let mut l = (owned << 6) & (owned << 12);
-        w |= l & (owned << 18);
-        w |= l & (owned >> 6);
-        l >>= 18;
-        w |= l & (owned << 6);
-        w |= l & (owned >> 18);
-
-        let mut r = (owned << 8) & (owned << 16);
-        w |= r & (owned << 24);
-        w |= r & (owned >> 8);
-        r >>= 24;
-        w |= r & (owned << 8);
-        w |= r & (owned >> 24);
+    fn possible(&self) -> u128 {
+        (self.all + self.size.bottom_row) & self.size.board_mask
+    }
 
-        w & (BOARD_MASK ^ all)
+    fn get_winning_positions(&self, owned: u128, all: u128) -> u128 {
+        // Most boards fit in 64 bits, where shifts are much cheaper
+        let w = if self.size.narrow() {
+            winning_positions(owned as u64, self.size.rows) as u128
+        } else {
+            winning_positions(owned, self.size.rows)
+        };
+        w & (self.size.board_mask ^ all)
     }
 
     pub fn get(&self, row: u8, col: u8) -> Option<i8> {
-        let mask = 1 << (col*7 + row);
+        let mask = 1 << (col*self.size.height() + row);
         let (white, black) = if self.moves & 1 == 0 {
             (self.owned, self.owned ^ self.all)
         } else {
@@ -211,10 +545,136 @@ impl Board {
     }
 }
 
+/// Bitboard operations shared by `u64`, used for speed where a board fits,
+/// and `u128`. `up` and `down` give 0 for shifts by the full width or more,
+/// which tall boards need.
+trait Bits: Copy + Default + Eq + Add<Output = Self> + Sub<Output = Self> + Not<Output = Self>
+    + BitAnd<Output = Self> + BitOr<Output = Self> + BitXor<Output = Self>
+    + Shl<u32, Output = Self> + Shr<u32, Output = Self>
+{
+    const WIDTH: u32;
+    /// The low bits of `bits`, which must hold the whole board.
+    fn from(bits: u128) -> Self;
+    fn up(self, n: u32) -> Self;
+    fn down(self, n: u32) -> Self;
+    fn swap_bytes(self) -> Self;
+    fn count_ones(self) -> u32;
+}
+
+impl Bits for u64 {
+    const WIDTH: u32 = 64;
+    fn from(bits: u128) -> Self { bits as u64 }
+    fn up(self, n: u32) -> Self { self.checked_shl(n).unwrap_or(0) }
+    fn down(self, n: u32) -> Self { self.checked_shr(n).unwrap_or(0) }
+    fn swap_bytes(self) -> Self { u64::swap_bytes(self) }
+    fn count_ones(self) -> u32 { u64::count_ones(self) }
+}
+
+impl Bits for u128 {
+    const WIDTH: u32 = 128;
+    fn from(bits: u128) -> Self { bits }
+    fn up(self, n: u32) -> Self { self.checked_shl(n).unwrap_or(0) }
+    fn down(self, n: u32) -> Self { self.checked_shr(n).unwrap_or(0) }
+    fn swap_bytes(self) -> Self { u128::swap_bytes(self) }
+    fn count_ones(self) -> u32 { u128::count_ones(self) }
+}
+
+/// `all` with a stone on top of the column starting at bit `shift`.
+fn add_stone<B: Bits>(all: B, shift: u32) -> B {
+    all | (all + (B::from(1) << shift))
+}
+
+/// `all` without the top stone of the column starting at bit `shift`.
+fn remove_stone<B: Bits>(all: B, rows: u8, shift: u32) -> B {
+    let column = ((B::from(1) << rows as u32) - B::from(1)) << shift;
+    all ^ ((all & column) + (B::from(1) << shift)) >> 1
+}
+
+/// Moves that do not lose at once, those that leave the fewest ways to win
+/// for the opponent first. With two threats to block there are none.
+fn safe_moves<B: Bits>(board: &Board) -> Vec<u8> {
+    let size = board.size;
+    let (owned, all) = (B::from(board.owned), B::from(board.all));
+    let board_mask = B::from(size.board_mask);
+    let (top, column, h) = (B::from(size.top_mask(0)), B::from(size.col_mask(0)), size.height() as u32);
+    let zero = B::default();
+
+    let mut possible = (all + B::from(size.bottom_row)) & board_mask;
+    let opponent = winning_positions(owned ^ all, size.rows) & (board_mask ^ all);
+    let forced = possible & opponent;
+    if forced != zero {
+        if forced & (forced - B::from(1)) != zero { return Vec::new() }
+        possible = forced;
+    }
+    let safe = possible & !(opponent >> 1);
+
+    let mut moves = size.move_order().filter(|&col| {
+        all & top << (col as u32 * h) == zero
+    }).filter(|&col| {
+        safe & column << (col as u32 * h) != zero
+    }).collect::<Vec<_>>();
+
+    moves.sort_by_key(|&col| {
+        let moved = safe & column << (col as u32 * h);
+        let wins = winning_positions(owned | moved, size.rows) & (board_mask ^ (all | moved));
+        -(wins.count_ones() as i8)
+    });
+    moves
+}
+
+/// Reflects `bits` left to right, given the mask of one column of height `h`.
+/// Every shift stays below the board's width, so none of them overflow.
+fn mirror<B: Bits>(bits: B, column: B, cols: u8, h: u8) -> B {
+    let (cols, h) = (cols as u32, h as u32);
+    // Columns of one byte each are reversed by reversing the bytes
+    if h == 8 { return bits.swap_bytes() >> (B::WIDTH - cols * 8) }
+    // Otherwise swap the columns pairwise from the outside in
+    (0..cols / 2).fold(bits, |bits, col| {
+        let delta = (cols - 1 - 2 * col) * h;
+        let swapped = ((bits >> delta) ^ bits) & (column << (col * h));
+        bits ^ swapped ^ (swapped << delta)
+    })
+}
+
+/// Cells that would complete four in a row for `owned` on a board with
+/// `rows` rows, including occupied and out of bounds ones.
+fn winning_positions<B: Bits>(owned: B, rows: u8) -> B {
+    // Plain shifts are cheaper, and only the tallest boards shift too far
+    if 3 * (rows as u32 + 2) < B::WIDTH {
+        lines(owned, rows, |bits, n| bits << n, |bits, n| bits >> n)
+    } else {
+        lines(owned, rows, B::up, B::down)
+    }
+}
+
+/// `winning_positions` with the given shifts.
+fn lines<B: Bits, U: Fn(B, u32) -> B, D: Fn(B, u32) -> B>(owned: B, rows: u8, up: U, down: D) -> B {
+    let h = rows as u32;
+    let mut w = up(owned, 1) & up(owned, 2) & up(owned, 3);
+
+    for &shift in &[h + 1, h, h + 2] {
+        let mut p = up(owned, shift) & up(owned, 2 * shift);
+        w = w | (p & up(owned, 3 * shift));
+        w = w | (p & down(owned, shift));
+        p = down(p, 3 * shift);
+        w = w | (p & up(owned, shift));
+        w = w | (p & down(owned, 3 * shift));
+    }
+    w
+}
+
+impl FromStr for Board {
+    type Err = ParseError;
+
+    fn from_str(moves: &str) -> Result<Self, Self::Err> {
+        Board::parse(Size::default(), moves)
+    }
+}
+
 impl fmt::Display for Board {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for row in (0..ROWS).rev() {
-            for col in 0..COLS {
+        for row in (0..self.size.rows).rev() {
+            for col in 0..self.size.cols {
                 match self.get(row, col) {
                     None => write!(f, ". ")?,
                     Some(WHITE) => write!(f, "W ")?,
@@ -224,10 +684,51 @@ impl fmt::Display for Board {
             }
             write!(f, "\n")?;
         }
-        for col in 0..COLS {
+        for col in 0..self.size.cols {
             write!(f, "{} ", col)?;
         }
         write!(f, "\n")?;
         Ok(())
     }
 }
+
+#[cfg(feature = "serde")]
+mod serialize {
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Dimensions {
+        cols: u8,
+        rows: u8,
+    }
+
+    /// Sizes are written as their dimensions.
+    impl Serialize for Size {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            Dimensions { cols: self.cols, rows: self.rows }.serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Size {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let Dimensions { cols, rows } = Dimensions::deserialize(deserializer)?;
+            Size::new(cols, rows).ok_or_else(|| de::Error::custom(GridError::Size(cols, rows)))
+        }
+    }
+
+    /// Boards are written in the one-line form of `Board::to_fen`, which
+    /// also records their size.
+    impl Serialize for Board {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(&self.to_fen())
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Board {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let fen = String::deserialize(deserializer)?;
+            Board::from_fen(&fen).map_err(de::Error::custom)
+        }
+    }
+}
@@ -1,29 +1,289 @@
+use std::io;
+use std::time::{Duration, Instant};
+
 use fnv::FnvHashSet;
 use board::*;
 use minimax::*;
+use record::*;
 
 pub struct Human;
 
+/// What a player does on their turn.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Turn {
+    /// Plays in a column, numbered from 0.
+    Move(u8),
+    /// Takes back moves until it is an interactive player's turn again.
+    Undo,
+    /// Replays moves that were taken back, until it is an interactive
+    /// player's turn again.
+    Redo,
+    /// Starts the game over.
+    Restart,
+    /// Gives up the game.
+    Resign,
+    /// Asks the opponent to agree to a draw, then takes the turn again if
+    /// they decline. Once declined, the player has to move before offering
+    /// again: a further offer is ignored and the player takes the turn again,
+    /// and a player that is not interactive forfeits on the one after.
+    OfferDraw,
+}
+
+/// How much time each side has for its moves. A side that takes longer
+/// loses on time.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TimeControl {
+    /// The same time for every move.
+    PerMove(Duration),
+    /// A fixed time for all of a side's moves.
+    SuddenDeath(Duration),
+    /// A time for all of a side's moves, extended after each move.
+    Increment { base: Duration, increment: Duration },
+}
+
+impl TimeControl {
+    fn start(self) -> Duration {
+        match self {
+            TimeControl::PerMove(time)
+            | TimeControl::SuddenDeath(time)
+            | TimeControl::Increment { base: time, .. } => time,
+        }
+    }
+
+    /// Time left after a turn that took `elapsed` with `remaining` left.
+    /// Only a turn that `moved` earns the increment or a new move's time.
+    fn after(self, remaining: Duration, elapsed: Duration, moved: bool) -> Duration {
+        match self {
+            TimeControl::PerMove(time) if moved => time,
+            TimeControl::Increment { increment, .. } if moved => remaining - elapsed + increment,
+            _ => remaining - elapsed,
+        }
+    }
+}
+
+/// Time a player has for the move it is asked to make.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TimeLeft {
+    pub remaining: Duration,
+    pub control: TimeControl,
+}
+
+impl TimeLeft {
+    /// A share of the remaining time to spend on a move in `board`, keeping a
+    /// margin for overhead.
+    pub fn budget(&self, board: &Board) -> Duration {
+        let budget = match self.control {
+            TimeControl::PerMove(_) => self.remaining,
+            TimeControl::SuddenDeath(_) | TimeControl::Increment { .. } => {
+                let increment = match self.control {
+                    TimeControl::Increment { increment, .. } => increment,
+                    _ => Duration::from_secs(0),
+                };
+                let moves = (board.size().cells() - board.moves + 1) as u32 / 2;
+                (self.remaining / moves.max(1) + increment).min(self.remaining)
+            }
+        };
+        budget * 4 / 5
+    }
+}
+
+/// One side of a game run by `Engine`. Every method but `take_turn` has a
+/// default that does nothing.
 pub trait Player {
-    fn take_turn(&mut self, board: &mut Board) -> u8;
+    /// Called before the first move of every game, including restarts, with
+    /// the player's color.
+    fn new_game(&mut self, _size: Size, _color: i8) {}
+
+    /// Chooses what to do in `board`, within `time` in a timed game.
+    fn take_turn(&mut self, board: &Board, time: Option<TimeLeft>) -> Turn;
+
+    /// Called after the opponent plays `col`, leaving `board`.
+    fn opponent_moved(&mut self, _board: &Board, _col: u8) {}
+
+    /// Called after an undo, redo or restart by either player leaves `board`.
+    fn history_changed(&mut self, _board: &Board) {}
+
+    /// Whether to agree to the opponent's offer of a draw in `board`.
+    fn accept_draw(&mut self, _board: &Board) -> bool { false }
+
+    /// Called once the game is over.
+    fn game_over(&mut self, _record: &GameRecord) {}
+
+    /// Whether the player is a person, whose turns undo and redo stop at.
+    fn is_interactive(&self) -> bool { false }
 }
 
 impl Player for Human {
-    fn take_turn(&mut self, board: &mut Board) -> u8 {
-        let mut col: u8 = read!();
+    fn take_turn(&mut self, board: &Board, time: Option<TimeLeft>) -> Turn {
+        if let Some(time) = time {
+            let remaining = time.remaining;
+            println!("{}.{:03}s left.", remaining.as_secs(), remaining.subsec_millis());
+        }
         let valid = board.valid_moves()
             .into_iter()
             .collect::<FnvHashSet<_>>();
-        while !valid.contains(&col) {
-            col = read!();
+        loop {
+            let input = match read_line() {
+                Some(input) => input,
+                None => {
+                    println!("Input closed. Resigning.");
+                    return Turn::Resign
+                }
+            };
+            match input.as_str() {
+                "undo" => return Turn::Undo,
+                "redo" => return Turn::Redo,
+                "restart" => return Turn::Restart,
+                "resign" => return Turn::Resign,
+                "draw" => return Turn::OfferDraw,
+                _ => match input.parse() {
+                    Ok(col) if valid.contains(&col) => return Turn::Move(col),
+                    _ => println!("Enter a column from 0 to {}, undo, redo, restart, resign or draw.",
+                                  board.size().cols() - 1),
+                },
+            }
         }
-        col
+    }
+
+    fn accept_draw(&mut self, _: &Board) -> bool {
+        println!("Your opponent offers a draw. Accept? (y/n)");
+        read_line().map_or(false, |answer| answer.starts_with('y'))
+    }
+
+    fn is_interactive(&self) -> bool { true }
+}
+
+/// Reads a line of standard input without its surrounding whitespace, or
+/// `None` once input is closed or cannot be read.
+fn read_line() -> Option<String> {
+    let mut line = String::new();
+    match io::stdin().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim().to_string()),
+    }
+}
+
+impl Player for Box<dyn Player> {
+    fn new_game(&mut self, size: Size, color: i8) {
+        (**self).new_game(size, color)
+    }
+
+    fn take_turn(&mut self, board: &Board, time: Option<TimeLeft>) -> Turn {
+        (**self).take_turn(board, time)
+    }
+
+    fn opponent_moved(&mut self, board: &Board, col: u8) {
+        (**self).opponent_moved(board, col)
+    }
+
+    fn history_changed(&mut self, board: &Board) {
+        (**self).history_changed(board)
+    }
+
+    fn accept_draw(&mut self, board: &Board) -> bool {
+        (**self).accept_draw(board)
+    }
+
+    fn game_over(&mut self, record: &GameRecord) {
+        (**self).game_over(record)
+    }
+
+    fn is_interactive(&self) -> bool {
+        (**self).is_interactive()
     }
 }
 
 impl Player for AI {
-    fn take_turn(&mut self, board: &mut Board) -> u8 {
-        self.solve(board)
+    /// Searches within the time budget wherever the opening book does not
+    /// have the position.
+    fn take_turn(&mut self, board: &Board, time: Option<TimeLeft>) -> Turn {
+        let mut board = board.clone();
+        let time = match time {
+            Some(time) => time,
+            None => return Turn::Move(self.solve(&mut board).expect("asked to move on a full board")),
+        };
+        let (book_miss, limits) = (self.book_miss(), self.limits());
+        let budget = Limits { depth: None, time: Some(time.budget(&board)) };
+        self.set_book_miss(BookMiss::Think(budget));
+        self.set_limits(Some(budget));
+        let col = self.solve(&mut board).expect("asked to move on a full board");
+        self.set_book_miss(book_miss);
+        self.set_limits(limits);
+        Turn::Move(col)
+    }
+}
+
+/// How a game run by `Engine` ended.
+#[derive(Clone, Debug)]
+pub struct Summary {
+    /// `WHITE` or `BLACK`, or `None` for a draw.
+    pub winner: Option<i8>,
+    pub termination: Termination,
+    pub board: Board,
+    /// The whole game, including every move.
+    pub record: GameRecord,
+}
+
+impl Summary {
+    /// Columns played, numbered from 0.
+    pub fn moves(&self) -> &[u8] {
+        &self.record.moves
+    }
+}
+
+/// Receives progress reports from `Engine::run`. Every method has a default
+/// that does nothing.
+pub trait Observer {
+    /// Called with the position before every turn and once the game is over.
+    fn position(&mut self, _board: &Board) {}
+
+    /// Called after `color` plays `col`.
+    fn moved(&mut self, _color: i8, _col: u8) {}
+
+    /// Called after `color` offers a draw and the opponent answers.
+    fn draw_offered(&mut self, _color: i8, _accepted: bool) {}
+
+    fn game_over(&mut self, _summary: &Summary) {}
+}
+
+/// Prints the game to standard output. The default observer.
+pub struct Printer;
+
+/// Reports nothing.
+pub struct Silent;
+
+impl Observer for Silent {}
+
+fn player(color: i8) -> &'static str {
+    if color == WHITE { "one" } else { "two" }
+}
+
+impl Observer for Printer {
+    fn position(&mut self, board: &Board) {
+        println!("{}", board);
+    }
+
+    fn moved(&mut self, color: i8, col: u8) {
+        println!("\nPlayer {} played column {}!", player(color), col);
+    }
+
+    fn draw_offered(&mut self, color: i8, accepted: bool) {
+        println!("\nPlayer {} offered a draw.", player(color));
+        if !accepted { println!("The offer was declined."); }
+    }
+
+    fn game_over(&mut self, summary: &Summary) {
+        let loser = summary.winner.map(|color| player(color ^ 1));
+        match (summary.termination, summary.winner, loser) {
+            (Termination::Time, _, Some(loser)) => println!("Game over. Player {} lost on time!", loser),
+            (Termination::Resignation, _, Some(loser)) => println!("Game over. Player {} resigned!", loser),
+            (Termination::Agreement, _, _) => println!("Game over. Players agreed to a draw!"),
+            (Termination::Forfeit, _, Some(loser)) => println!("Game over. Player {} forfeited!", loser),
+            (Termination::IllegalMove, _, Some(loser)) => println!("Game over. Player {} made an illegal move!", loser),
+            (_, Some(WHITE), _) => println!("Game over. White won!"),
+            (_, Some(_), _) => println!("Game over. Black won!"),
+            (_, None, _) => println!("Game over. Players tied!"),
+        }
     }
 }
 
@@ -31,52 +291,296 @@ pub struct Engine<P1: Player, P2: Player> {
     board: Board,
     player_one: P1,
     player_two: P2,
+    observer: Box<dyn Observer>,
+    record: GameRecord,
+    /// Moves taken back by `Turn::Undo`, most recent last.
+    undone: Vec<u8>,
+    /// Position that `Turn::Restart` returns to.
+    start: Board,
+    /// Whether the side to move has had a draw declined since it last moved.
+    declined: bool,
+    /// Whether a declined draw has been offered again since the last move.
+    repeated: bool,
+    control: Option<TimeControl>,
+    /// Time left for White and Black.
+    clocks: [Duration; 2],
 }
 
-pub fn human_vs_human() -> Engine<Human, Human> {
-    Engine {
-        board: Board::new(),
-        player_one: Human,
-        player_two: Human,
-    }
+/// Sets up an `Engine` between any two players, from any position. Sides
+/// without a player are played by `Human`.
+pub struct EngineBuilder {
+    board: Board,
+    players: [Option<(String, Box<dyn Player>)>; 2],
+    control: Option<TimeControl>,
+    observer: Box<dyn Observer>,
 }
 
-pub fn human_vs_cpu() -> Engine<AI, Human> {
-    Engine {
-        board: Board::new(),
-        player_one: AI::new(),
-        player_two: Human,
+impl EngineBuilder {
+    /// A game on the standard board between two humans, printed as it goes.
+    pub fn new() -> Self {
+        EngineBuilder {
+            board: Board::new(),
+            players: [None, None],
+            control: None,
+            observer: Box::new(Printer),
+        }
+    }
+
+    /// Starts from the empty board of `size`.
+    pub fn size(self, size: Size) -> Self {
+        self.position(Board::with_size(size))
+    }
+
+    /// Starts from `board`. An AI player must be created for its size.
+    pub fn position(mut self, board: Board) -> Self {
+        self.board = board;
+        self
+    }
+
+    /// Lets `player`, recorded as `name`, play `color`, which must be `WHITE`
+    /// or `BLACK`.
+    pub fn player(mut self, color: i8, name: &str, player: Box<dyn Player>) -> Self {
+        assert!(color == WHITE || color == BLACK, "player color must be WHITE or BLACK, not {}", color);
+        self.players[color as usize] = Some((name.to_string(), player));
+        self
     }
-}
 
-pub fn cpu_vs_cpu() -> Engine<AI, AI> {
-    Engine {
-        board: Board::new(),
-        player_one: AI::new(),
-        player_two: AI::new(),
+    pub fn time_control(mut self, control: TimeControl) -> Self {
+        self.control = Some(control);
+        self
     }
+
+    pub fn observer<O: Observer + 'static>(mut self, observer: O) -> Self {
+        self.observer = Box::new(observer);
+        self
+    }
+
+    pub fn build(self) -> Engine<Box<dyn Player>, Box<dyn Player>> {
+        let [white, black] = self.players;
+        let (white_name, white) = white.unwrap_or_else(|| ("Human".to_string(), Box::new(Human)));
+        let (black_name, black) = black.unwrap_or_else(|| ("Human".to_string(), Box::new(Human)));
+        let mut engine = Engine::from_position(self.board, white, black).named(&white_name, &black_name);
+        engine.observer = self.observer;
+        match self.control {
+            Some(control) => engine.with_time_control(control),
+            None => engine,
+        }
+    }
+}
+
+impl Default for EngineBuilder {
+    fn default() -> Self { Self::new() }
+}
+
+pub fn human_vs_human(size: Size) -> Engine<Human, Human> {
+    Engine::new(size, Human, Human).named("Human", "Human")
+}
+
+pub fn human_vs_cpu(size: Size, difficulty: Difficulty) -> Engine<AI, Human> {
+    let mut ai = AI::with_size(size);
+    ai.set_difficulty(difficulty);
+    Engine::new(size, ai, Human).named("Computer", "Human")
+}
+
+pub fn cpu_vs_cpu(size: Size) -> Engine<AI, AI> {
+    Engine::new(size, AI::with_size(size), AI::with_size(size)).named("Computer", "Computer")
 }
 
 impl<P1: Player, P2: Player> Engine<P1, P2> {
-    pub fn run(mut self) {
+    /// A game between `player_one`, who plays White and moves first, and
+    /// `player_two`.
+    pub fn new(size: Size, player_one: P1, player_two: P2) -> Self {
+        Self::from_position(Board::with_size(size), player_one, player_two)
+    }
+
+    fn from_position(board: Board, player_one: P1, player_two: P2) -> Self {
+        Engine {
+            board: board.clone(),
+            player_one,
+            player_two,
+            observer: Box::new(Printer),
+            record: GameRecord::from_position(board.clone(), "White", "Black"),
+            undone: Vec::new(),
+            start: board,
+            declined: false,
+            repeated: false,
+            control: None,
+            clocks: [Duration::from_secs(0); 2],
+        }
+    }
+
+    /// Plays under `control` instead of without time limits.
+    pub fn with_time_control(mut self, control: TimeControl) -> Self {
+        self.control = Some(control);
+        self.clocks = [control.start(); 2];
+        self
+    }
+
+    /// Reports progress to `observer` instead of printing it.
+    pub fn with_observer<O: Observer + 'static>(mut self, observer: O) -> Self {
+        self.observer = Box::new(observer);
+        self
+    }
+
+    fn named(mut self, white: &str, black: &str) -> Self {
+        self.record.white = white.to_string();
+        self.record.black = black.to_string();
+        self
+    }
+
+    /// Plays the game to the end, reporting progress to the observer.
+    pub fn run(mut self) -> Summary {
+        self.new_game();
         loop {
-            println!("{}", self.board);
-            if let Some(color) = self.board.was_won() {
-                let name = if color == WHITE { "White" } else { "Black" };
-                println!("Game over. {} won!", name);
-                break
-            } else if self.board.valid_moves().len() == 0 {
-                println!("Game over. Players tied!");
-                break
-            } else if self.board.moves & 1 == WHITE {
-                let col = self.player_one.take_turn(&mut self.board);
-                println!("\nPlayer one played column {}!", col);
-                self.board.make_move(col);
+            self.observer.position(&self.board);
+            if self.record.outcome != Outcome::Ongoing { break }
+
+            let color = self.board.moves & 1;
+            let side = color as usize;
+            let time = self.control.map(|control| TimeLeft { remaining: self.clocks[side], control });
+            let start = Instant::now();
+            let turn = if color == WHITE {
+                self.player_one.take_turn(&self.board, time)
+            } else {
+                self.player_two.take_turn(&self.board, time)
+            };
+            let winner = if color == WHITE { Outcome::BlackWon } else { Outcome::WhiteWon };
+
+            let elapsed = start.elapsed();
+            if let Some(control) = self.control {
+                if elapsed > self.clocks[side] {
+                    self.record.finish(winner, Termination::Time);
+                    break
+                }
+                let moved = matches!(turn, Turn::Move(_));
+                self.clocks[side] = control.after(self.clocks[side], elapsed, moved);
+            }
+
+            if let Turn::OfferDraw = turn {
+                if self.declined {
+                    // People are asked again, but a bot could offer forever
+                    let interactive = if color == WHITE {
+                        self.player_one.is_interactive()
+                    } else {
+                        self.player_two.is_interactive()
+                    };
+                    if self.repeated && !interactive {
+                        self.record.finish(winner, Termination::Forfeit);
+                        break
+                    }
+                    self.repeated = true;
+                    continue
+                }
             } else {
-                let col = self.player_two.take_turn(&mut self.board);
-                println!("\nPlayer two played column {}!", col);
-                self.board.make_move(col);
+                self.declined = false;
+                self.repeated = false;
+            }
+
+            match turn {
+                Turn::Move(col) => {
+                    if self.board.play(col).is_err() {
+                        self.record.finish(winner, Termination::IllegalMove);
+                        break
+                    }
+                    self.record.push_timed(col, elapsed).unwrap();
+                    self.undone.clear();
+                    self.observer.moved(color, col);
+                    if color == WHITE {
+                        self.player_two.opponent_moved(&self.board, col);
+                    } else {
+                        self.player_one.opponent_moved(&self.board, col);
+                    }
+                }
+                Turn::Undo => {
+                    self.undo();
+                    self.history_changed();
+                }
+                Turn::Redo => {
+                    self.redo();
+                    self.history_changed();
+                }
+                Turn::Restart => {
+                    self.restart();
+                    self.history_changed();
+                }
+                Turn::Resign => {
+                    self.record.finish(winner, Termination::Resignation);
+                    break
+                }
+                Turn::OfferDraw => {
+                    let accepted = if color == WHITE {
+                        self.player_two.accept_draw(&self.board)
+                    } else {
+                        self.player_one.accept_draw(&self.board)
+                    };
+                    self.observer.draw_offered(color, accepted);
+                    if accepted {
+                        self.record.finish(Outcome::Draw, Termination::Agreement);
+                        break
+                    }
+                    self.declined = true;
+                }
             }
         }
+
+        self.player_one.game_over(&self.record);
+        self.player_two.game_over(&self.record);
+        let summary = Summary {
+            winner: match self.record.outcome {
+                Outcome::WhiteWon => Some(WHITE),
+                Outcome::BlackWon => Some(BLACK),
+                Outcome::Draw | Outcome::Ongoing => None,
+            },
+            termination: self.record.termination,
+            board: self.board,
+            record: self.record,
+        };
+        self.observer.game_over(&summary);
+        summary
+    }
+
+    fn new_game(&mut self) {
+        let size = self.board.size();
+        self.player_one.new_game(size, WHITE);
+        self.player_two.new_game(size, BLACK);
+    }
+
+    fn history_changed(&mut self) {
+        self.player_one.history_changed(&self.board);
+        self.player_two.history_changed(&self.board);
+    }
+
+    fn is_interactive(&self) -> bool {
+        if self.board.moves & 1 == WHITE {
+            self.player_one.is_interactive()
+        } else {
+            self.player_two.is_interactive()
+        }
+    }
+
+    fn undo(&mut self) {
+        while let Some(col) = self.record.pop() {
+            self.board.undo_move(col);
+            self.undone.push(col);
+            if self.is_interactive() { break }
+        }
+    }
+
+    fn redo(&mut self) {
+        while let Some(col) = self.undone.pop() {
+            self.board.make_move(col);
+            self.record.push(col).unwrap();
+            if self.is_interactive() || self.board.was_won().is_some() { break }
+        }
+    }
+
+    fn restart(&mut self) {
+        self.record = GameRecord::from_position(self.start.clone(), &self.record.white, &self.record.black);
+        self.board = self.start.clone();
+        self.undone.clear();
+        if let Some(control) = self.control {
+            self.clocks = [control.start(); 2];
+        }
+        self.new_game();
     }
 }
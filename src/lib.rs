@@ -1,8 +1,11 @@
-#[macro_use]
-extern crate text_io;
 extern crate fnv;
+extern crate rand;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
 pub mod board;
 pub mod engine;
 pub mod minimax;
-mod opening;
+pub mod opening;
+pub mod record;
 mod table;
@@ -1,50 +1,86 @@
 extern crate ggez;
 extern crate minimax;
 
+use std::env;
 use std::path::PathBuf;
+use std::time::Instant;
 
 use ggez::*;
 use ggez::event::*;
-use ggez::graphics::{Font};
+use ggez::graphics::{Font, Text};
 
 use minimax::board::*;
-use minimax::minimax::AI;
+use minimax::minimax::{AI, Difficulty};
+use minimax::record::GameRecord;
 
 struct Game {
+    size: Size,
     ai: AI,
     board: Board,
+    record: GameRecord,
+    turn: Instant,
     font: Font,
+    /// Difficulty label, rebuilt only when the difficulty changes.
+    level: Text,
     next: u8,
     ready: bool,
     done: bool,
 }
 
-const WIDTH: u32 = 1175;
-const HEIGHT: u32 = 1000;
+/// Column placeholder while waiting for the player's next move.
+const NO_MOVE: u8 = ::std::u8::MAX;
+
+/// File that S appends the current game to.
+const ARCHIVE: &'static str = "games.txt";
+
+/// Columns are picked with the keys 1 to 9.
+const MAX_COLS: u8 = 9;
 
 const STROKE: f32 = 2.0;
 const RADIUS: f32 = 60.0;
 const OFFSET: f32 = 100.0;
 
 impl Game {
-    fn new(ctx: &mut Context) -> GameResult<Game> {
+    fn new(ctx: &mut Context, size: Size) -> GameResult<Game> {
         graphics::set_background_color(ctx, (70, 114, 186, 255).into());
 
+        let ai = AI::with_size(size);
+        let font = graphics::Font::new(ctx, "/OpenSans-Regular.ttf", 36)?;
+        let level = level_text(ctx, &font, ai.difficulty())?;
         Ok(Game {
-            ai: AI::new(),
-            board: Board::new(),
-            font: graphics::Font::new(ctx, "/OpenSans-Regular.ttf", 36)?,
-            next: 8,
+            size,
+            ai,
+            board: Board::with_size(size),
+            record: GameRecord::new(size, "Computer", "Human"),
+            turn: Instant::now(),
+            font,
+            level,
+            next: NO_MOVE,
             ready: false,
             done: false,
         }) 
     }
 
+    fn set_difficulty(&mut self, ctx: &mut Context, difficulty: Difficulty) -> GameResult<()> {
+        self.ai.set_difficulty(difficulty);
+        self.level = level_text(ctx, &self.font, difficulty)?;
+        Ok(())
+    }
+
+}
+
+fn level_text(ctx: &mut Context, font: &Font, difficulty: Difficulty) -> GameResult<Text> {
+    Text::new(ctx, &format!("Difficulty: {:?} (F1-F5 to change)", difficulty), font)
 }
 
-fn to_point(row: u8, col: u8) -> graphics::Point2 {
+fn dimensions(size: Size) -> (u32, u32) {
+    let cell = (OFFSET + RADIUS) as u32;
+    (cell * size.cols() as u32 + 55, cell * size.rows() as u32 + 40)
+}
+
+fn to_point(size: Size, row: u8, col: u8) -> graphics::Point2 {
     let col = col as f32;
-    let row = (ROWS - row - 1) as f32;
+    let row = (size.rows() - row - 1) as f32;
     graphics::Point2::new(
         OFFSET + (OFFSET + RADIUS)*col,
         OFFSET + (OFFSET + RADIUS)*row,
@@ -56,15 +92,20 @@ impl event::EventHandler for Game {
         if self.board.was_won().is_some() {
             return Ok(())
         } else if self.ready && self.done {
-            if self.board.valid_moves().contains(&self.next) {
-                self.board.make_move(self.next);
+            if self.board.play(self.next).is_ok() {
+                self.record.push_timed(self.next, self.turn.elapsed()).unwrap();
+                self.turn = Instant::now();
                 self.ready = false;
                 self.done = false;
-                self.next = 8;
+                self.next = NO_MOVE;
             }
         } else if !self.done {
-            let next = self.ai.solve(&mut self.board);
-            self.board.make_move(next);
+            // On a full board the game is drawn and there is nothing to play
+            if let Some(next) = self.ai.solve(&mut self.board) {
+                self.board.make_move(next);
+                self.record.push_timed(next, self.turn.elapsed()).unwrap();
+                self.turn = Instant::now();
+            }
             self.done = true;
         }
         Ok(())
@@ -73,8 +114,8 @@ impl event::EventHandler for Game {
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
         graphics::clear(ctx);
         
-        for row in (0..ROWS).rev() {
-            for col in 0..COLS {
+        for row in (0..self.size.rows()).rev() {
+            for col in 0..self.size.cols() {
                 let color = match self.board.get(row, col) {
                     Some(_) => graphics::Color::from_rgb(0, 0, 0),
                 _           => graphics::Color::from_rgb(90, 154, 254),
@@ -84,16 +125,24 @@ impl event::EventHandler for Game {
                     _           => graphics::DrawMode::Fill,
                 };
                 graphics::set_color(ctx, graphics::Color::from_rgb(255, 255, 255))?;
-                graphics::circle(ctx, graphics::DrawMode::Fill, to_point(row, col), RADIUS, 0.01)?;
+                graphics::circle(ctx, graphics::DrawMode::Fill, to_point(self.size, row, col), RADIUS, 0.01)?;
                 graphics::set_color(ctx, color)?;
-                graphics::circle(ctx, fill, to_point(row, col), RADIUS, 0.01)?
+                graphics::circle(ctx, fill, to_point(self.size, row, col), RADIUS, 0.01)?
             } 
         }
-        if let Some(color) = self.board.was_won() {
-            let winner     = if color == WHITE { "White" } else { "Black "};
-            let over_text  = graphics::Text::new(ctx, &format!("Game over! {} won!", winner), &self.font)?;
-            let win_text   = graphics::Text::new(ctx, "Press W or B to play again as white or black.", &self.font)?;
-            let (x, y)     = ((WIDTH / 2) as f32, (HEIGHT / 2) as f32);
+        graphics::set_color(ctx, graphics::Color::from_rgb(255, 255, 255))?;
+        graphics::draw(ctx, &self.level, graphics::Point2::new(10.0, 0.0), 0.0)?;
+
+        let over = match self.board.was_won() {
+            Some(color) => Some(format!("Game over! {} won!", if color == WHITE { "White" } else { "Black "})),
+            None if self.board.valid_moves().is_empty() => Some("Game over! It's a draw!".to_string()),
+            None => None,
+        };
+        if let Some(over) = over {
+            let over_text  = graphics::Text::new(ctx, &over, &self.font)?;
+            let win_text   = graphics::Text::new(ctx, "Press S to save, or W or B to play again as white or black.", &self.font)?;
+            let (w, h)     = dimensions(self.size);
+            let (x, y)     = ((w / 2) as f32, (h / 2) as f32);
             let (ox, oy)   = ((over_text.width() / 2) as f32, (over_text.height() / 2) as f32);
             let (wx, wy)   = ((win_text.width() / 2) as f32, (win_text.height() / 2) as f32);
             let over_point = graphics::Point2::new(x - ox, y - (oy * 2.25));
@@ -134,15 +183,52 @@ impl event::EventHandler for Game {
             Keycode::Num7 => {
                 self.next = 6;
             }
-            Keycode::W => {
-                self.board = Board::new();
+            Keycode::Num8 => {
+                self.next = 7;
+            }
+            Keycode::Num9 => {
                 self.next = 8;
+            }
+            Keycode::F1 => {
+                self.set_difficulty(ctx, Difficulty::Random).unwrap();
+                return
+            }
+            Keycode::F2 => {
+                self.set_difficulty(ctx, Difficulty::Greedy).unwrap();
+                return
+            }
+            Keycode::F3 => {
+                self.set_difficulty(ctx, Difficulty::Depth(8)).unwrap();
+                return
+            }
+            Keycode::F4 => {
+                self.set_difficulty(ctx, Difficulty::Mistakes(0.2)).unwrap();
+                return
+            }
+            Keycode::F5 => {
+                self.set_difficulty(ctx, Difficulty::Perfect).unwrap();
+                return
+            }
+            Keycode::S => {
+                match self.record.append(ARCHIVE) {
+                    Ok(()) => println!("Saved game to {}.", ARCHIVE),
+                    Err(err) => println!("Could not save game: {}", err),
+                }
+                return
+            }
+            Keycode::W => {
+                self.board = Board::with_size(self.size);
+                self.record = GameRecord::new(self.size, "Human", "Computer");
+                self.turn = Instant::now();
+                self.next = NO_MOVE;
                 self.ready = false;
                 self.done = true;
             }
             Keycode::B => {
-                self.board = Board::new();
-                self.next = 8;
+                self.board = Board::with_size(self.size);
+                self.record = GameRecord::new(self.size, "Computer", "Human");
+                self.turn = Instant::now();
+                self.next = NO_MOVE;
                 self.ready = false;
                 self.done = false;
             }
@@ -154,12 +240,33 @@ impl event::EventHandler for Game {
 }
 
 
+/// Reads an optional `COLS ROWS` board size from the command line, with at
+/// most `MAX_COLS` columns.
+fn parse_size() -> Option<Size> {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    match args.len() {
+        0 => Some(Size::default()),
+        2 => Size::new(args[0].parse().ok()?, args[1].parse().ok()?)
+            .filter(|size| size.cols() <= MAX_COLS),
+        _ => None,
+    }
+}
+
 pub fn main() {
+    let size = match parse_size() {
+        Some(size) => size,
+        None => {
+            println!("Usage: connect-four [COLS ROWS], with at most {} columns", MAX_COLS);
+            return
+        }
+    };
+
+    let (width, height) = dimensions(size);
     let cb = ContextBuilder::new("connect-four", "nwtnni")
         .window_setup(conf::WindowSetup::default()
                       .title("Connect Four"))
         .window_mode(conf::WindowMode::default()
-                     .dimensions(WIDTH, HEIGHT));
+                     .dimensions(width, height));
 
     let ctx = &mut cb.build().unwrap();
 
@@ -169,7 +276,7 @@ pub fn main() {
     path.push("resources");
     ctx.filesystem.mount(&path, true);
 
-    match Game::new(ctx) {
+    match Game::new(ctx, size) {
         Err(_) => {
             println!("Could not load game.");
         }
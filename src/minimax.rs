@@ -1,37 +1,328 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::{self, Rng, SeedableRng, XorShiftRng};
+
 use board::*;
 use table::*;
 use opening::*;
 
 pub const SIZE: i8 = (ROWS*COLS) as i8;
 
+/// Scale of proven results in a depth-limited search on a board of `cells`.
+/// `Board::heuristic` counts empty cells, so it never reaches this.
+fn proven(cells: i8) -> i32 { cells as i32 + 1 }
+
+/// Score for winning with the next move after `moves` moves. Widened first,
+/// since `cells + 1` overflows an `i8` on a board of 127 cells.
+fn immediate_win(cells: i8, moves: i8) -> i8 { ((cells as i16 + 1 - moves as i16) / 2) as i8 }
+
+/// How often, in nodes, a depth-limited search checks the clock.
+const CHECK_INTERVAL: u64 = 1024;
+
+/// Fewest empty cells at which the search shares table entries between a
+/// position and its mirror image. Nearer the end, a hit saves too little to
+/// pay for mirroring every position.
+const MIRROR_DEPTH: i8 = 16;
+
+/// Bounds on a depth-limited search. Unset limits are unbounded.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Limits {
+    /// Maximum depth in plies.
+    pub depth: Option<u8>,
+    /// Wall-clock budget for the whole search.
+    pub time: Option<Duration>,
+}
+
+struct Clock {
+    deadline: Option<Instant>,
+    nodes: u64,
+}
+
+impl Clock {
+    fn expired(&mut self) -> bool {
+        self.nodes += 1;
+        match self.deadline {
+            None => false,
+            Some(deadline) => self.nodes % CHECK_INTERVAL == 0 && Instant::now() >= deadline,
+        }
+    }
+}
+
+/// Result of playing a column, from the point of view of the side to move.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Evaluation {
+    /// The column is full.
+    Illegal,
+    /// The column was pruned by `Board::safe_moves`: the opponent wins with
+    /// their next stone. Carries the exact score of the move.
+    Pruned(i8),
+    /// Exact score of the move, as returned by `AI::score`.
+    Score(i8),
+}
+
+impl Evaluation {
+    /// Exact score of the move, if it is legal.
+    pub fn score(&self) -> Option<i8> {
+        match *self {
+            Evaluation::Illegal => None,
+            Evaluation::Pruned(score) | Evaluation::Score(score) => Some(score),
+        }
+    }
+}
+
+/// Strength of the moves chosen by `AI::solve`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Difficulty {
+    /// Plays a uniformly random legal move.
+    Random,
+    /// Wins immediately if possible, and otherwise plays the safe move with
+    /// the best `Board::heuristic` one ply ahead.
+    Greedy,
+    /// Looks this many plies ahead using `AI::think`.
+    Depth(u8),
+    /// Plays perfectly.
+    Perfect,
+    /// Plays perfectly, except for a random legal move with this probability.
+    Mistakes(f64),
+}
+
+/// What `Difficulty::Perfect` does when the opening book has no entry for a
+/// position within its depth. Positions past the book are always solved.
+#[derive(Copy, Clone, Debug)]
+pub enum BookMiss {
+    /// Solves the position, however long that takes.
+    Search,
+    /// Picks a move with `AI::think` within these limits.
+    Think(Limits),
+}
+
 pub struct AI {
-    table: Table,
-    lookup: Lookup,
+    size: Size,
+    threads: usize,
+    table: Arc<Table>,
+    lookup: Option<Lookup>,
+    book_miss: BookMiss,
+    limits: Option<Limits>,
+    difficulty: Difficulty,
+    rng: XorShiftRng,
+}
+
+impl Default for AI {
+    fn default() -> Self { Self::new() }
 }
 
 impl AI {
     pub fn new() -> Self {
-        AI { table: Table::new(), lookup: Lookup::new() }
+        Self::with_size(Size::default())
+    }
+
+    /// Creates a perfect AI for boards of the given size. The opening book
+    /// only covers the standard size.
+    pub fn with_size(size: Size) -> Self {
+        Self::with_table(size, Table::new(size))
+    }
+
+    /// Like `with_size`, with a transposition table of at most `bytes`, or
+    /// `min_table_bytes(size)` if that is more. `table_bytes` tells which.
+    pub fn with_budget(size: Size, bytes: usize) -> Self {
+        Self::with_table(size, Table::with_budget(size, bytes))
+    }
+
+    /// Smallest transposition table for `size`, below which `with_budget`
+    /// goes over budget. Only sizes of more than 106 `Size::bits` need more
+    /// than a few bytes.
+    pub fn min_table_bytes(size: Size) -> usize {
+        Table::min_bytes(size)
+    }
+
+    fn with_table(size: Size, table: Table) -> Self {
+        let lookup = Some(Lookup::new()).filter(|lookup| lookup.size() == size);
+        AI {
+            size,
+            threads: 1,
+            table: Arc::new(table),
+            lookup,
+            book_miss: BookMiss::Search,
+            limits: None,
+            difficulty: Difficulty::Perfect,
+            rng: Self::rng(rand::random()),
+        }
+    }
+
+    pub fn with_difficulty(difficulty: Difficulty) -> Self {
+        let mut ai = Self::new();
+        ai.difficulty = difficulty;
+        ai
+    }
+
+    /// Creates an AI whose random choices are reproducible from `seed`.
+    pub fn seeded(difficulty: Difficulty, seed: u64) -> Self {
+        let mut ai = Self::with_difficulty(difficulty);
+        ai.reseed(seed);
+        ai
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = Self::rng(seed);
+    }
+
+    fn rng(seed: u64) -> XorShiftRng {
+        let (lo, hi) = (seed as u32, (seed >> 32) as u32);
+
+        // Xorshift state must not be all zero
+        XorShiftRng::from_seed([lo, hi, lo ^ 0x9E3779B9, hi ^ 0x7F4A7C15])
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Sets how many threads exact searches use. All of them share one
+    /// transposition table.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    /// Memory taken by the transposition table.
+    pub fn table_bytes(&self) -> usize {
+        self.table.bytes()
+    }
+
+    /// Forgets everything learned so far, reusing the table's memory.
+    pub fn clear_table(&mut self) {
+        self.table.clear();
+    }
+
+    /// Saves the transposition table so a later session can resume from it.
+    pub fn save_table<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.table.save(path)
+    }
+
+    /// Replaces the transposition table with one written by `save_table`.
+    pub fn load_table<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.table = Arc::new(Table::load(path, self.size)?);
+        Ok(())
+    }
+
+    pub fn book_miss(&self) -> BookMiss {
+        self.book_miss
+    }
+
+    pub fn set_book_miss(&mut self, book_miss: BookMiss) {
+        self.book_miss = book_miss;
+    }
+
+    pub fn limits(&self) -> Option<Limits> {
+        self.limits
+    }
+
+    /// Makes `Difficulty::Perfect` pick moves past the opening book with
+    /// `AI::think` within `limits`, or solve them exactly if `None`.
+    pub fn set_limits(&mut self, limits: Option<Limits>) {
+        self.limits = limits;
+    }
+
+    pub fn difficulty(&self) -> Difficulty {
+        self.difficulty
+    }
+
+    pub fn set_difficulty(&mut self, difficulty: Difficulty) {
+        self.difficulty = difficulty;
+    }
+
+    /// Picks a move at the current difficulty, or returns `None` if the
+    /// board is full.
+    pub fn solve(&mut self, board: &mut Board) -> Option<u8> {
+        self.check(board);
+        if board.valid_moves().is_empty() { return None }
+        let col = match self.difficulty {
+            Difficulty::Random => self.random(board),
+            Difficulty::Greedy => self.greedy(board),
+            Difficulty::Depth(depth) => self.think(board, Limits { depth: Some(depth), time: None }),
+            Difficulty::Perfect => self.perfect(board),
+            Difficulty::Mistakes(p) => {
+                if self.rng.gen::<f64>() < p {
+                    self.random(board)
+                } else {
+                    self.perfect(board)
+                }
+            }
+        };
+        Some(col)
+    }
+
+    fn check(&self, board: &Board) {
+        debug_assert!(board.size() == self.size, "board size does not match AI");
     }
 
-    pub fn solve(&mut self, board: &mut Board) -> u8 {
+    fn random(&mut self, board: &Board) -> u8 {
+        *self.rng.choose(&board.valid_moves()).unwrap()
+    }
+
+    fn greedy(&mut self, board: &mut Board) -> u8 {
+        let valid = board.valid_moves();
+        for &col in &valid {
+            if board.will_win(col) { return col }
+        }
+
+        let mut moves = board.safe_moves();
+        if moves.is_empty() { moves = valid }
+
+        let mut best_score = i32::MIN;
+        let mut best_col = moves[0];
+        for col in moves {
+            board.make_move(col);
+            let score = -board.heuristic();
+            board.undo_move(col);
+
+            if score > best_score {
+                best_score = score;
+                best_col = col;
+            }
+        }
+        best_col
+    }
+
+    fn perfect(&mut self, board: &mut Board) -> u8 {
         let safe = board.safe_moves();
-        let mut best_score = SIZE;
-        let mut best_col = 3;
+        let mut best_score = board.size().cells();
 
         for &col in &safe {
             if board.will_win(col) { return col }
         }
 
-        for &col in &safe {
+        // Every move loses immediately
+        if safe.is_empty() { return board.valid_moves()[0] }
+        let mut best_col = safe[0];
 
-            let score = if board.moves < 6 {
-                board.make_move(col);
-                let score = self.lookup.get(board.key());
-                board.undo_move(col);
-                score
-            } else {
-                self.negamax(board, -1, 1)
+        let book = safe.iter().map(|&col| self.book(board, col)).collect::<Vec<_>>();
+        if let BookMiss::Think(limits) = self.book_miss {
+            if book.contains(&Some(None)) { return self.think(board, limits) }
+        }
+        if let Some(limits) = self.limits {
+            if book.contains(&None) { return self.think(board, limits) }
+        }
+
+        let workers = self.workers();
+        for (&col, &score) in safe.iter().zip(&book) {
+            let score = match score {
+                Some(Some(score)) => score,
+                _ => {
+                    board.make_move(col);
+                    let score = self.weak(&workers, board, -1, 1);
+                    board.undo_move(col);
+                    score
+                }
             };
 
             if score < best_score {
@@ -39,30 +330,417 @@ impl AI {
                 best_col = col;
             }
         }
-        return best_col
+        best_col
     }
 
-    pub fn negamax(&mut self, board: &mut Board, mut alpha: i8, mut beta: i8) -> i8 {
-        let moves = board.safe_moves();
+    /// Book score of playing `col`, for the opponent, if the book has it, or
+    /// `None` if there is no book or the move leads past its depth.
+    fn book(&self, board: &mut Board, col: u8) -> Option<Option<i8>> {
+        match self.lookup {
+            Some(ref lookup) if board.moves < lookup.depth() as i8 => {
+                board.make_move(col);
+                let score = lookup.get(board.canonical_key());
+                board.undo_move(col);
+                Some(score)
+            }
+            _ => None,
+        }
+    }
+
+    /// Strongly solves `board`, returning its exact score from the point of
+    /// view of the side to move: positive if it wins, negative if it loses,
+    /// and zero for a draw. A win scores one plus the number of stones the
+    /// winner has left after playing its last stone, so faster wins score
+    /// higher and slower losses score closer to zero.
+    ///
+    /// The game must not already be over.
+    pub fn score(&mut self, board: &mut Board) -> i8 {
+        let workers = self.workers();
+        self.strong(&workers, board)
+    }
+
+    fn strong(&mut self, workers: &Option<Workers>, board: &mut Board) -> i8 {
+        self.check(board);
+        self.table.next_age();
+        let size = board.size().cells();
+        for col in board.valid_moves() {
+            if board.will_win(col) { return immediate_win(size, board.moves) }
+        }
+
+        let mut min = -(size - board.moves) / 2;
+        let mut max = immediate_win(size, board.moves);
 
-        if moves.len() == 0 { return -1 }
-        if board.moves >= SIZE - 2 { return 0 }
-        if alpha >= beta { return alpha }
+        // Null-window searches, biased towards zero where most scores lie
+        while min < max {
+            let mut med = min + (max - min) / 2;
+            if med <= 0 && min / 2 < med {
+                med = min / 2;
+            } else if med >= 0 && max / 2 > med {
+                med = max / 2;
+            }
+
+            let score = self.search(workers, board, med, med + 1);
+            if score <= med { max = score } else { min = score }
+        }
+        min
+    }
+
+    /// Scores every column of `board`, indexed by column.
+    pub fn analyze(&mut self, board: &Board) -> Vec<Evaluation> {
+        self.check(board);
+        let mut board = board.clone();
+        let size = board.size().cells();
+        let valid = board.valid_moves();
+        let safe = board.safe_moves();
+        let workers = self.workers();
+        (0..board.size().cols()).map(|col| {
+            if !valid.contains(&col) {
+                Evaluation::Illegal
+            } else if board.will_win(col) {
+                Evaluation::Score(immediate_win(size, board.moves))
+            } else if !safe.contains(&col) {
+                Evaluation::Pruned(-(size - board.moves) / 2)
+            } else {
+                board.make_move(col);
+                let score = -self.strong(&workers, &mut board);
+                board.undo_move(col);
+                Evaluation::Score(score)
+            }
+        }).collect()
+    }
+
+    /// Returns the expected line of play from `board` until the end of the
+    /// game, assuming both sides play perfectly: the winner takes the fastest
+    /// win and the loser holds out as long as possible.
+    pub fn principal_variation(&mut self, board: &Board) -> Vec<u8> {
+        let mut board = board.clone();
+        let mut line = Vec::new();
+        let workers = self.workers();
+        while board.was_won().is_none() && board.moves < board.size().cells() {
+            let col = self.best_move(&workers, &mut board);
+            board.make_move(col);
+            line.push(col);
+        }
+        line
+    }
+
+    /// Finds a column achieving the exact score of `board`.
+    fn best_move(&mut self, workers: &Option<Workers>, board: &mut Board) -> u8 {
+        let valid = board.valid_moves();
+        for &col in &valid {
+            if board.will_win(col) { return col }
+        }
+
+        // Every move loses immediately
+        let safe = board.safe_moves();
+        if safe.is_empty() { return valid[0] }
+
+        let score = self.strong(workers, board);
+        for &col in &safe {
+            board.make_move(col);
+            let reply = self.search(workers, board, -score, -score + 1);
+            board.undo_move(col);
+            if reply <= -score { return col }
+        }
+        unreachable!()
+    }
 
-        if let Some(score) = self.table.get(board.key()) {
-            if beta > score { beta = score }
+    /// Picks a move by iterative deepening within `limits`, returning the best
+    /// move of the deepest completed iteration. Positions beyond the horizon
+    /// are scored with `Board::heuristic`.
+    ///
+    /// The game must not already be over.
+    pub fn think(&mut self, board: &mut Board, limits: Limits) -> u8 {
+        self.check(board);
+        let size = board.size().cells();
+        let valid = board.valid_moves();
+        for &col in &valid {
+            if board.will_win(col) { return col }
+        }
+
+        let mut moves = board.safe_moves();
+        if moves.is_empty() { return valid[0] }
+        if moves.len() == 1 { return moves[0] }
+
+        let mut clock = Clock {
+            deadline: limits.time.map(|time| Instant::now() + time),
+            nodes: 0,
         };
 
+        let remaining = (size - board.moves) as u8;
+        let max_depth = limits.depth.map_or(remaining, |depth| depth.min(remaining));
+        let mut best_col = moves[0];
+
+        for depth in 1..max_depth + 1 {
+            let mut alpha = -proven(size) * size as i32;
+            let beta = proven(size) * size as i32;
+            let mut iteration = None;
+
+            for &col in &moves {
+                board.make_move(col);
+                let score = self.deepen(board, depth - 1, -beta, -alpha, &mut clock);
+                board.undo_move(col);
+
+                let score = match score {
+                    None => { iteration = None; break }
+                    Some(score) => -score,
+                };
+
+                if iteration.is_none() || score > alpha {
+                    alpha = score;
+                    iteration = Some(col);
+                }
+            }
+
+            match iteration {
+                None => break,
+                Some(col) => best_col = col,
+            }
+
+            // Search the previous best move first next iteration
+            moves.retain(|&col| col != best_col);
+            moves.insert(0, best_col);
+
+            if alpha.abs() >= proven(size) { break }
+        }
+        best_col
+    }
+
+    fn deepen(&mut self, board: &mut Board, depth: u8, mut alpha: i32, beta: i32, clock: &mut Clock) -> Option<i32> {
+        if clock.expired() { return None }
+
+        let size = board.size().cells();
+        let moves = board.safe_moves();
+
+        if moves.is_empty() { return Some(-proven(size) * ((size - board.moves) / 2) as i32) }
+        if board.moves >= size - 2 { return Some(0) }
+        if depth == 0 { return Some(board.heuristic()) }
+
         for col in moves {
             board.make_move(col);
-            let score = -self.negamax(board, -beta, -alpha);
+            let score = self.deepen(board, depth - 1, -beta, -alpha, clock);
             board.undo_move(col);
 
-            if score >= beta { return beta }
+            let score = -score?;
+            if score >= beta { return Some(beta) }
             if score > alpha { alpha = score }
         }
+        Some(alpha)
+    }
+
+    /// Weakly solves `board`: 1 for a win, 0 for a draw, and -1 for a loss.
+    pub fn negamax(&mut self, board: &mut Board, alpha: i8, beta: i8) -> i8 {
+        let workers = self.workers();
+        self.weak(&workers, board, alpha, beta)
+    }
+
+    fn weak(&mut self, workers: &Option<Workers>, board: &mut Board, alpha: i8, beta: i8) -> i8 {
+        self.check(board);
+        self.table.next_age();
+        self.search(workers, board, alpha, beta).signum()
+    }
+
+    /// Helper threads for every search of one call, if there are several.
+    fn workers(&self) -> Option<Workers> {
+        if self.threads <= 1 { None } else { Some(Workers::spawn(&self.table, self.threads)) }
+    }
+
+    fn search(&mut self, workers: &Option<Workers>, board: &mut Board, alpha: i8, beta: i8) -> i8 {
+        match *workers {
+            None => alpha_beta(&self.table, &AtomicBool::new(false), board, alpha, beta),
+            Some(ref workers) => self.split(workers, board, alpha, beta),
+        }
+    }
+
+    /// Searches the first move at the root alone, then shares the remaining
+    /// moves between threads, which abort as soon as one of them fails high.
+    fn split(&mut self, workers: &Workers, board: &mut Board, alpha: i8, beta: i8) -> i8 {
+        let moves = board.safe_moves();
+        let (alpha, beta, key) = match window(&self.table, board, &moves, alpha, beta) {
+            Ok(window) => window,
+            Err(score) => return score,
+        };
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let first = moves[0];
+        board.make_move(first);
+        let score = -alpha_beta(&self.table, &stop, board, -beta, -alpha);
+        board.undo_move(first);
+
+        if score >= beta {
+            store(&self.table, board, key, alpha, beta, beta);
+            return beta
+        }
+
+        let job = Job {
+            board: board.clone(),
+            moves: Arc::new(moves),
+            next: Arc::new(AtomicUsize::new(1)),
+            best: Arc::new(AtomicIsize::new(alpha.max(score) as isize)),
+            stop,
+            beta,
+        };
+        workers.run(&job, self.threads.min(job.moves.len() - 1));
+
+        let best = (job.best.load(Ordering::Relaxed) as i8).min(beta);
+        store(&self.table, board, key, alpha, beta, best);
+        best
+    }
+}
+
+/// Threads that share the moves of each split between them, spawned once
+/// for a whole call into the AI.
+struct Workers {
+    jobs: Vec<mpsc::Sender<Job>>,
+    done: mpsc::Receiver<bool>,
+    handles: Vec<thread::JoinHandle<()>>,
+}
+
+impl Workers {
+    fn spawn(table: &Arc<Table>, count: usize) -> Self {
+        let (finished, done) = mpsc::channel();
+        let (mut jobs, mut handles) = (Vec::new(), Vec::new());
+        for _ in 0..count {
+            let (sender, receiver) = mpsc::channel::<Job>();
+            let (table, finished) = (table.clone(), finished.clone());
+            handles.push(thread::spawn(move || {
+                for mut job in receiver {
+                    let _done = Done(&finished);
+                    job.run(&table);
+                }
+            }));
+            jobs.push(sender);
+        }
+        Workers { jobs, done, handles }
+    }
+
+    /// Gives `job` to the first `count` threads and waits for all of them.
+    fn run(&self, job: &Job, count: usize) {
+        for sender in &self.jobs[..count] {
+            sender.send(job.clone()).expect("search thread panicked");
+        }
+        for _ in 0..count {
+            if self.done.recv() != Ok(false) { panic!("search thread panicked") }
+        }
+    }
+}
 
-        self.table.insert(board.key(), alpha);
-        return alpha
+impl Drop for Workers {
+    fn drop(&mut self) {
+        self.jobs.clear();
+        for handle in self.handles.drain(..) {
+            // A panic has already been reported by `run`
+            let _ = handle.join();
+        }
     }
 }
+
+/// Reports that a job ended, and whether it panicked, when dropped.
+struct Done<'a>(&'a mpsc::Sender<bool>);
+
+impl<'a> Drop for Done<'a> {
+    fn drop(&mut self) {
+        let _ = self.0.send(thread::panicking());
+    }
+}
+
+/// The moves left at a split, shared by the threads searching them.
+#[derive(Clone)]
+struct Job {
+    board: Board,
+    moves: Arc<Vec<u8>>,
+    next: Arc<AtomicUsize>,
+    best: Arc<AtomicIsize>,
+    stop: Arc<AtomicBool>,
+    beta: i8,
+}
+
+impl Job {
+    fn run(&mut self, table: &Table) {
+        loop {
+            let index = self.next.fetch_add(1, Ordering::Relaxed);
+            if index >= self.moves.len() || self.stop.load(Ordering::Relaxed) { break }
+
+            let alpha = self.best.load(Ordering::Relaxed) as i8;
+            let col = self.moves[index];
+            self.board.make_move(col);
+            let score = -alpha_beta(table, &self.stop, &mut self.board, -self.beta, -alpha);
+            self.board.undo_move(col);
+
+            if self.stop.load(Ordering::Relaxed) { break }
+            if score >= self.beta { self.stop.store(true, Ordering::Relaxed) }
+            self.best.fetch_max(score as isize, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Narrows the search window at `board`, along with its key for `store`, or
+/// returns its score if no search is needed.
+fn window(table: &Table, board: &Board, moves: &[u8], mut alpha: i8, mut beta: i8) -> Result<(i8, i8, u128), i8> {
+    let size = board.size().cells();
+
+    if moves.is_empty() { return Err(-(size - board.moves) / 2) }
+    if board.moves >= size - 2 { return Err(0) }
+
+    let mut min = -(size - 2 - board.moves) / 2;
+    let mut max = (size - 1 - board.moves) / 2;
+    let key = if size - board.moves >= MIRROR_DEPTH { board.canonical_key() } else { board.key() };
+    match table.get(key) {
+        Some((score, Bound::Exact)) => return Err(score),
+        Some((score, Bound::Lower)) => min = min.max(score),
+        Some((score, Bound::Upper)) => max = max.min(score),
+        None => (),
+    }
+
+    if alpha < min {
+        alpha = min;
+        if alpha >= beta { return Err(alpha) }
+    }
+
+    if beta > max {
+        beta = max;
+        if alpha >= beta { return Err(beta) }
+    }
+    Ok((alpha, beta, key))
+}
+
+/// Stores the result of searching `board`, whose key is `key`, within
+/// `(alpha, beta)`.
+fn store(table: &Table, board: &Board, key: u128, alpha: i8, beta: i8, score: i8) {
+    let depth = (board.size().cells() - board.moves) as u8;
+    let bound = if score >= beta {
+        Bound::Lower
+    } else if score > alpha {
+        Bound::Exact
+    } else {
+        Bound::Upper
+    };
+    table.insert(key, score, bound, depth);
+}
+
+/// Alpha-beta search returning exact scores within the window. Gives up
+/// without touching the table once `stop` is set.
+fn alpha_beta(table: &Table, stop: &AtomicBool, board: &mut Board, alpha: i8, beta: i8) -> i8 {
+    let moves = board.safe_moves();
+    let (alpha, beta, key) = match window(table, board, &moves, alpha, beta) {
+        Ok(window) => window,
+        Err(score) => return score,
+    };
+
+    let mut best = alpha;
+    for col in moves {
+        board.make_move(col);
+        let score = -alpha_beta(table, stop, board, -beta, -best);
+        board.undo_move(col);
+
+        if stop.load(Ordering::Relaxed) { return best }
+        if score >= beta {
+            best = beta;
+            break
+        }
+        if score > best { best = score }
+    }
+
+    store(table, board, key, alpha, beta, best);
+    best
+}
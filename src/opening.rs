@@ -1,25 +1,229 @@
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
 use std::str::FromStr;
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHashSet};
 
 use board::*;
+use minimax::AI;
 
+/// Identifies binary books.
+const MAGIC: &[u8; 4] = b"C4OB";
+
+/// Bumped whenever the binary layout or the meaning of keys changes.
+const VERSION: u32 = 4;
+
+/// Bytes before the keys: magic, version, columns, rows, depth and entry
+/// count.
+const HEADER: usize = 4 + 4 + 1 + 1 + 1 + 8;
+
+/// Opening book in the binary format written by `to_binary`, queried in
+/// place by binary search over its sorted keys.
 pub struct Lookup {
-    table: FnvHashMap<u64, i8>
+    bytes: Cow<'static, [u8]>,
+    size: Size,
+    depth: u8,
+    count: usize,
+}
+
+impl Default for Lookup {
+    fn default() -> Self { Self::new() }
 }
 
 impl Lookup {
+    /// The book for the standard board, embedded in the binary.
     pub fn new() -> Self {
-        let mut table = FnvHashMap::default();
-        for line in include_str!("opening.dat").trim_right().split("\n") {
-            let mut parts = line.split_whitespace();
-            let board = Board::from(parts.next().unwrap());
-            let score = i8::from_str(parts.next().unwrap()).unwrap();
-            table.insert(board.key(), score);
+        Self::from_bytes(Cow::Borrowed(include_bytes!("opening.bin")))
+            .expect("embedded opening book is invalid")
+    }
+
+    /// Checks the header of a binary book, returning `None` if it is not
+    /// one or is truncated.
+    pub fn from_bytes(bytes: Cow<'static, [u8]>) -> Option<Self> {
+        if bytes.len() < HEADER || &bytes[..4] != MAGIC { return None }
+        if read_u32(&bytes[4..]) != VERSION { return None }
+
+        let size = Size::new(bytes[8], bytes[9])?;
+        let depth = bytes[10];
+        let count = read_u64(&bytes[11..]) as usize;
+        if count.checked_mul(17).and_then(|len| len.checked_add(HEADER)) != Some(bytes.len()) {
+            return None
+        }
+        Some(Lookup { bytes, size, depth, count })
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    /// Number of moves in the longest lines of the book, which covers every
+    /// position up to that many moves.
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    /// Weak score for the side to move of the position with canonical key
+    /// `key`, if the book has it.
+    pub fn get(&self, key: u128) -> Option<i8> {
+        let (mut lo, mut hi) = (0, self.count);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let found = read_u128(&self.bytes[HEADER + mid * 16..]);
+            if found == key {
+                return Some(self.bytes[HEADER + self.count * 16 + mid] as i8)
+            } else if found < key {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        None
+    }
+}
+
+/// Converts a text book, as read by `parse`, to the binary format: the
+/// header, then every distinct canonical key in ascending order, then the
+/// score of each key. Integers are little-endian.
+pub fn to_binary(book: &str, size: Size) -> Result<Vec<u8>, BookError> {
+    let lines = replay(book, size)?;
+    let depth = lines.iter().map(|&(moves, _, _)| moves.len()).max().unwrap_or(0);
+    let mut entries = lines.into_iter()
+        .map(|(_, board, score)| (board.canonical_key(), score))
+        .collect::<FnvHashMap<_, _>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    entries.sort();
+
+    let mut bytes = Vec::with_capacity(HEADER + entries.len() * 17);
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&[size.cols(), size.rows(), depth as u8]);
+    bytes.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+    for &(key, _) in &entries {
+        bytes.extend_from_slice(&key.to_le_bytes());
+    }
+    for &(_, score) in &entries {
+        bytes.push(score as u8);
+    }
+    Ok(bytes)
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut word = [0; 4];
+    word.copy_from_slice(&bytes[..4]);
+    u32::from_le_bytes(word)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(word)
+}
+
+fn read_u128(bytes: &[u8]) -> u128 {
+    let mut word = [0; 16];
+    word.copy_from_slice(&bytes[..16]);
+    u128::from_le_bytes(word)
+}
+
+/// Why a text book could not be read.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BookError {
+    /// A line, numbered from 1, that is not a move string and a score of
+    /// -1, 0 or 1.
+    Line(usize),
+    /// A move string that cannot be played on the book's board size, by its
+    /// line.
+    Moves(usize, ParseError),
+}
+
+impl fmt::Display for BookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BookError::Line(line) => write!(f, "malformed line {}", line),
+            BookError::Moves(line, err) => write!(f, "{} on line {}", err, line),
         }
-        Lookup { table }
     }
+}
+
+impl Error for BookError {}
+
+/// Reads a book of lines made of a move string, with columns numbered from
+/// 1, and the weak score of the resulting position for the side to move.
+pub fn parse(book: &str) -> Result<Vec<(&str, i8)>, BookError> {
+    numbered(book).map(|line| line.map(|(_, moves, score)| (moves, score))).collect()
+}
+
+/// Lines of `book` with their numbers, skipping blank ones.
+fn numbered(book: &str) -> impl Iterator<Item = Result<(usize, &str, i8), BookError>> {
+    book.lines().enumerate().filter(|&(_, line)| !line.trim().is_empty()).map(|(number, line)| {
+        let mut parts = line.split_whitespace();
+        let moves = parts.next();
+        let score = parts.next().and_then(|score| i8::from_str(score).ok());
+        match (moves, score, parts.next()) {
+            (Some(moves), Some(score), None) if score.abs() <= 1 => Ok((number + 1, moves, score)),
+            _ => Err(BookError::Line(number + 1)),
+        }
+    })
+}
+
+/// Plays out the move string of every line of `book` on the board of
+/// `size`.
+fn replay(book: &str, size: Size) -> Result<Vec<(&str, Board, i8)>, BookError> {
+    numbered(book).map(|line| {
+        let (number, moves, score) = line?;
+        let board = Board::parse(size, moves).map_err(|err| BookError::Moves(number, err))?;
+        Ok((moves, board, score))
+    }).collect()
+}
+
+/// Weakly solves every position reachable within `depth` moves on the AI's
+/// board size, excluding finished games. Each position appears once, under
+/// its first move string in order of length and then column, and mirror
+/// images are left out since they share a canonical key. Returns `None` on
+/// boards of more than 9 columns, whose moves have no digit.
+pub fn generate(ai: &mut AI, depth: u8) -> Option<Vec<(String, i8)>> {
+    let size = ai.size();
+    if size.cols() > 9 { return None }
+
+    let mut seen = FnvHashSet::default();
+    let mut book = Vec::new();
+    let mut frontier = vec![String::new()];
+
+    for _ in 0..depth {
+        let mut next = Vec::new();
+        for moves in frontier {
+            let board = Board::parse(size, &moves).expect("generated an invalid move string");
+            let valid = board.valid_moves();
+            for col in (0..size.cols()).filter(|col| valid.contains(col)) {
+                let mut child = board.clone();
+                child.make_move(col);
+                if child.was_won().is_some() || !seen.insert(child.canonical_key()) { continue }
+
+                let moves = format!("{}{}", moves, col + 1);
+                book.push((moves.clone(), solve(ai, &mut child)));
+                next.push(moves);
+            }
+        }
+        frontier = next;
+    }
+    Some(book)
+}
+
+/// Re-solves every line of `book`, returning the move string, book score
+/// and solved score of each line that disagrees.
+pub fn verify(ai: &mut AI, book: &str) -> Result<Vec<(String, i8, i8)>, BookError> {
+    let lines = replay(book, ai.size())?;
+    Ok(lines.into_iter().filter_map(|(moves, mut board, expected)| {
+        let score = solve(ai, &mut board);
+        if score == expected { None } else { Some((moves.to_string(), expected, score)) }
+    }).collect())
+}
 
-    pub fn get(&self, key: u64) -> i8 {
-        self.table[&key]
+fn solve(ai: &mut AI, board: &mut Board) -> i8 {
+    if board.valid_moves().into_iter().any(|col| board.will_win(col)) {
+        1
+    } else {
+        ai.negamax(board, -1, 1)
     }
 }
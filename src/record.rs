@@ -0,0 +1,490 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use board::*;
+
+/// Result of a game.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Outcome {
+    WhiteWon,
+    BlackWon,
+    Draw,
+    /// The game has not finished.
+    Ongoing,
+}
+
+impl Outcome {
+    /// The outcome of the game in `board` so far.
+    pub fn of(board: &Board) -> Self {
+        match board.was_won() {
+            Some(WHITE) => Outcome::WhiteWon,
+            Some(_) => Outcome::BlackWon,
+            None if board.moves == board.size().cells() => Outcome::Draw,
+            None => Outcome::Ongoing,
+        }
+    }
+
+    fn tag(self) -> &'static str {
+        match self {
+            Outcome::WhiteWon => "1-0",
+            Outcome::BlackWon => "0-1",
+            Outcome::Draw => "1/2-1/2",
+            Outcome::Ongoing => "*",
+        }
+    }
+
+    fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "1-0" => Some(Outcome::WhiteWon),
+            "0-1" => Some(Outcome::BlackWon),
+            "1/2-1/2" => Some(Outcome::Draw),
+            "*" => Some(Outcome::Ongoing),
+            _ => None,
+        }
+    }
+}
+
+/// Why a game ended.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Termination {
+    /// Four in a row or a full board, or the game has not finished.
+    Normal,
+    /// A player resigned.
+    Resignation,
+    /// The players agreed to a draw.
+    Agreement,
+    /// A player ran out of time.
+    Time,
+    /// A player kept offering a draw after it was declined.
+    Forfeit,
+    /// A player made an illegal move.
+    IllegalMove,
+}
+
+impl Termination {
+    fn tag(self) -> &'static str {
+        match self {
+            Termination::Normal => "normal",
+            Termination::Resignation => "resignation",
+            Termination::Agreement => "agreement",
+            Termination::Time => "time",
+            Termination::Forfeit => "forfeit",
+            Termination::IllegalMove => "illegal move",
+        }
+    }
+
+    fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "normal" => Some(Termination::Normal),
+            "resignation" => Some(Termination::Resignation),
+            "agreement" => Some(Termination::Agreement),
+            "time" => Some(Termination::Time),
+            "forfeit" => Some(Termination::Forfeit),
+            "illegal move" => Some(Termination::IllegalMove),
+            _ => None,
+        }
+    }
+}
+
+/// A game as played: who played it, the position it started from if not the
+/// empty board, the columns played from 0 with the time spent on each move
+/// in milliseconds if known, how it ended, and when it started and finished
+/// in seconds since the Unix epoch.
+///
+/// `Display` writes a game in a format modeled on chess PGN, which `FromStr`
+/// and `GameRecord::parse_all` read back:
+///
+/// ```text
+/// [White "Alice"]
+/// [Black "Bob"]
+/// [Date "2018.06.01 12:00:00"]
+/// [EndDate "2018.06.01 12:05:30"]
+/// [Size "7x6"]
+/// [Result "1-0"]
+/// [Termination "normal"]
+///
+/// 1. 4 {1.250} 4 {0.800} 2. 5 3 ... 1-0
+/// ```
+///
+/// Dates are UTC and `EndDate` is left out for unfinished games. Games that
+/// start from another position have a `FEN` tag with the form written by
+/// `Board::to_fen`, after `Size`. Moves are
+/// columns numbered from 1, each optionally followed by the seconds spent on
+/// it in braces, and the move list ends with the result.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GameRecord {
+    pub white: String,
+    pub black: String,
+    pub size: Size,
+    pub start: Option<Board>,
+    pub moves: Vec<u8>,
+    /// Milliseconds spent on each of `moves`.
+    pub times: Vec<Option<u64>>,
+    pub outcome: Outcome,
+    pub termination: Termination,
+    pub started: u64,
+    pub finished: Option<u64>,
+}
+
+impl GameRecord {
+    /// Starts recording a game now.
+    pub fn new(size: Size, white: &str, black: &str) -> Self {
+        GameRecord {
+            white: white.to_string(),
+            black: black.to_string(),
+            size,
+            start: None,
+            moves: Vec::new(),
+            times: Vec::new(),
+            outcome: Outcome::Ongoing,
+            termination: Termination::Normal,
+            started: now(),
+            finished: None,
+        }
+    }
+
+    /// Starts recording a game from `board` now.
+    pub fn from_position(board: Board, white: &str, black: &str) -> Self {
+        let mut record = Self::new(board.size(), white, black);
+        let outcome = Outcome::of(&board);
+        if board.moves > 0 { record.start = Some(board); }
+        if outcome != Outcome::Ongoing { record.finish(outcome, Termination::Normal); }
+        record
+    }
+
+    /// Records a legal move, finishing the record if it ends the game.
+    pub fn push(&mut self, col: u8) -> Result<MoveOutcome, MoveError> {
+        self.push_move(col, None)
+    }
+
+    /// Records a legal move that took `time` to choose.
+    pub fn push_timed(&mut self, col: u8, time: Duration) -> Result<MoveOutcome, MoveError> {
+        self.push_move(col, Some(time.as_secs() * 1000 + time.subsec_millis() as u64))
+    }
+
+    fn push_move(&mut self, col: u8, millis: Option<u64>) -> Result<MoveOutcome, MoveError> {
+        if self.outcome != Outcome::Ongoing { return Err(MoveError::GameOver) }
+        let mut board = self.board()?;
+        let outcome = board.play(col)?;
+        self.moves.push(col);
+        self.times.push(millis);
+        if outcome != MoveOutcome::Ongoing {
+            self.finish(Outcome::of(&board), Termination::Normal);
+        }
+        Ok(outcome)
+    }
+
+    /// Takes back the last move, returning its column.
+    pub fn pop(&mut self) -> Option<u8> {
+        let col = self.moves.pop()?;
+        self.times.pop();
+        self.outcome = Outcome::Ongoing;
+        self.termination = Termination::Normal;
+        self.finished = None;
+        Some(col)
+    }
+
+    /// Marks the game as over, such as when a player resigns.
+    pub fn finish(&mut self, outcome: Outcome, termination: Termination) {
+        self.outcome = outcome;
+        self.termination = termination;
+        self.finished = Some(now());
+    }
+
+    /// Replays the moves, failing on the first illegal one.
+    pub fn board(&self) -> Result<Board, MoveError> {
+        let mut board = self.start.clone().unwrap_or_else(|| Board::with_size(self.size));
+        for &col in &self.moves {
+            board.play(col)?;
+        }
+        Ok(board)
+    }
+
+    /// Reads every game in `text`, in the format written by `Display`.
+    /// Games are separated by their results.
+    pub fn parse_all(text: &str) -> Result<Vec<Self>, RecordError> {
+        let mut games = Vec::new();
+        let mut lines = text.lines().enumerate().peekable();
+        loop {
+            while lines.peek().map_or(false, |&(_, line)| line.trim().is_empty()) {
+                lines.next();
+            }
+            if lines.peek().is_none() { return Ok(games) }
+
+            let mut tags = Vec::new();
+            while let Some(&(number, line)) = lines.peek() {
+                let line = line.trim();
+                if !line.starts_with('[') { break }
+                tags.push(parse_tag(line).ok_or(RecordError::Line(number + 1))?);
+                lines.next();
+            }
+            let mut game = from_tags(&tags)?;
+
+            let mut ended = false;
+            for (number, line) in lines.by_ref() {
+                ended = parse_moves(&mut game, line, number + 1)?;
+                if ended { break }
+            }
+            if !ended { return Err(RecordError::Unterminated) }
+            games.push(game);
+        }
+    }
+
+    /// Appends the game to the archive at `path`, creating it if needed.
+    pub fn append<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", self)
+    }
+
+    /// Reads every game in the archive at `path`.
+    pub fn load_all<P: AsRef<Path>>(path: P) -> io::Result<Vec<Self>> {
+        let text = fs::read_to_string(path)?;
+        Self::parse_all(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+/// Why a game record could not be read.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RecordError {
+    /// A malformed tag or move list on the given line, numbered from 1.
+    Line(usize),
+    /// A required tag is missing.
+    MissingTag(&'static str),
+    /// A tag has a value that could not be understood.
+    InvalidTag(String),
+    /// An illegal move, by its index in the game.
+    Move(usize, MoveError),
+    /// The result disagrees with the final position.
+    Result,
+    /// The move list does not end with a result.
+    Unterminated,
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RecordError::Line(line) => write!(f, "malformed line {}", line),
+            RecordError::MissingTag(tag) => write!(f, "missing tag {}", tag),
+            RecordError::InvalidTag(ref tag) => write!(f, "invalid value for tag {}", tag),
+            RecordError::Move(index, err) => write!(f, "{} at move {}", err, index),
+            RecordError::Result => write!(f, "result disagrees with the final position"),
+            RecordError::Unterminated => write!(f, "move list does not end with a result"),
+        }
+    }
+}
+
+impl Error for RecordError {}
+
+impl fmt::Display for GameRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "[White \"{}\"]", escape(&self.white))?;
+        writeln!(f, "[Black \"{}\"]", escape(&self.black))?;
+        writeln!(f, "[Date \"{}\"]", format_date(self.started))?;
+        if let Some(finished) = self.finished {
+            writeln!(f, "[EndDate \"{}\"]", format_date(finished))?;
+        }
+        writeln!(f, "[Size \"{}x{}\"]", self.size.cols(), self.size.rows())?;
+        if let Some(ref start) = self.start {
+            writeln!(f, "[FEN \"{}\"]", start.to_fen())?;
+        }
+        writeln!(f, "[Result \"{}\"]", self.outcome.tag())?;
+        writeln!(f, "[Termination \"{}\"]", self.termination.tag())?;
+        writeln!(f)?;
+
+        for (index, (&col, &millis)) in self.moves.iter().zip(&self.times).enumerate() {
+            if index % 2 == 0 { write!(f, "{}. ", index / 2 + 1)?; }
+            write!(f, "{} ", col + 1)?;
+            if let Some(millis) = millis {
+                write!(f, "{{{}.{:03}}} ", millis / 1000, millis % 1000)?;
+            }
+        }
+        writeln!(f, "{}", self.outcome.tag())
+    }
+}
+
+impl FromStr for GameRecord {
+    type Err = RecordError;
+
+    /// Reads a single game.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut games = Self::parse_all(text)?;
+        match games.len() {
+            0 => Err(RecordError::Unterminated),
+            1 => Ok(games.pop().unwrap()),
+            _ => Err(RecordError::Line(text.lines().count())),
+        }
+    }
+}
+
+/// Splits `[Name "value"]` into its name and value.
+fn parse_tag(line: &str) -> Option<(&str, String)> {
+    let inner = line.trim_start_matches('[').trim_end_matches(']');
+    if inner.len() + 2 != line.len() { return None }
+    let space = inner.find(' ')?;
+    let (name, value) = (&inner[..space], inner[space..].trim());
+    if value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') { return None }
+    Some((name, unescape(&value[1..value.len() - 1])))
+}
+
+/// Escapes quotes and backslashes in a tag value.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        unescaped.push(if c == '\\' { chars.next().unwrap_or(c) } else { c });
+    }
+    unescaped
+}
+
+fn from_tags(tags: &[(&str, String)]) -> Result<GameRecord, RecordError> {
+    let find = |name: &'static str| {
+        tags.iter().find(|&&(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    };
+    let require = |name: &'static str| find(name).ok_or(RecordError::MissingTag(name));
+    let invalid = |name: &str| RecordError::InvalidTag(name.to_string());
+
+    let size = require("Size")?;
+    let mut dimensions = size.split('x').map(u8::from_str);
+    let size = match (dimensions.next(), dimensions.next(), dimensions.next()) {
+        (Some(Ok(cols)), Some(Ok(rows)), None) => Size::new(cols, rows),
+        _ => None,
+    }.ok_or_else(|| invalid("Size"))?;
+
+    let start = match find("FEN") {
+        Some(fen) => match Board::from_fen(fen) {
+            Ok(board) if board.size() == size => Some(board),
+            _ => return Err(invalid("FEN")),
+        },
+        None => None,
+    };
+
+    let finished = match find("EndDate") {
+        Some(date) => Some(parse_date(date).ok_or_else(|| invalid("EndDate"))?),
+        None => None,
+    };
+
+    Ok(GameRecord {
+        white: require("White")?.to_string(),
+        black: require("Black")?.to_string(),
+        size,
+        start,
+        moves: Vec::new(),
+        times: Vec::new(),
+        outcome: Outcome::from_tag(require("Result")?).ok_or_else(|| invalid("Result"))?,
+        termination: match find("Termination") {
+            Some(tag) => Termination::from_tag(tag).ok_or_else(|| invalid("Termination"))?,
+            None => Termination::Normal,
+        },
+        started: parse_date(require("Date")?).ok_or_else(|| invalid("Date"))?,
+        finished,
+    })
+}
+
+/// Reads the moves on one line of a move list into `game`, returning whether
+/// the line ended with the result.
+fn parse_moves(game: &mut GameRecord, line: &str, number: usize) -> Result<bool, RecordError> {
+    let mut board = game.board().map_err(|err| RecordError::Move(game.moves.len(), err))?;
+    let mut tokens = line.split_whitespace();
+    while let Some(token) = tokens.next() {
+        if let Some(outcome) = Outcome::from_tag(token) {
+            if outcome != game.outcome || tokens.next().is_some() { return Err(RecordError::Line(number)) }
+            let position = Outcome::of(&board);
+            if position != Outcome::Ongoing && position != outcome { return Err(RecordError::Result) }
+            return Ok(true)
+        } else if token.starts_with('{') && token.ends_with('}') {
+            let millis = parse_seconds(&token[1..token.len() - 1]).ok_or(RecordError::Line(number))?;
+            match game.times.last_mut() {
+                Some(time @ &mut None) => *time = Some(millis),
+                _ => return Err(RecordError::Line(number)),
+            }
+        } else if token.ends_with('.') && token[..token.len() - 1].parse::<usize>().is_ok() {
+            continue
+        } else {
+            let col = token.parse::<u8>().map_err(|_| RecordError::Line(number))?;
+            let index = game.moves.len();
+            if col == 0 { return Err(RecordError::Move(index, MoveError::OutOfRange(col))) }
+            board.play(col - 1).map_err(|err| RecordError::Move(index, err))?;
+            game.moves.push(col - 1);
+            game.times.push(None);
+        }
+    }
+    Ok(false)
+}
+
+/// Reads seconds with up to millisecond precision, such as `1.250`.
+fn parse_seconds(text: &str) -> Option<u64> {
+    let mut parts = text.splitn(2, '.');
+    let seconds = parts.next()?.parse::<u64>().ok()?;
+    let millis = match parts.next() {
+        Some(fraction) if fraction.len() <= 3 && fraction.chars().all(|c| c.is_ascii_digit()) => {
+            format!("{:0<3}", fraction).parse::<u64>().ok()?
+        }
+        Some(_) => return None,
+        None => 0,
+    };
+    Some(seconds * 1000 + millis)
+}
+
+/// Formats seconds since the Unix epoch as a UTC `YYYY.MM.DD hh:mm:ss`.
+fn format_date(time: u64) -> String {
+    let (days, seconds) = ((time / 86400) as i64, time % 86400);
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}.{:02}.{:02} {:02}:{:02}:{:02}",
+            year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+/// Reads a date written by `format_date`.
+fn parse_date(date: &str) -> Option<u64> {
+    let mut parts = date.split(&['.', ' ', ':'][..]).map(u64::from_str);
+    let mut next = || parts.next().and_then(|part| part.ok());
+    let (year, month, day) = (next()?, next()?, next()?);
+    let (hour, minute, second) = (next()?, next()?, next()?);
+    if next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&day) { return None }
+    if hour > 23 || minute > 59 || second > 59 { return None }
+    let days = days_from_civil(year as i64, month, day);
+    // Days past the end of their month would roll over into the next one
+    if days < 0 || civil_from_days(days) != (year as i64, month, day) { return None }
+    Some(days as u64 * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+// Conversions between days since the Unix epoch and proleptic Gregorian
+// dates, from Howard Hinnant's `chrono`-compatible date algorithms.
+
+fn days_from_civil(year: i64, month: u64, day: u64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) as i64 / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, u64, u64) {
+    let days = days + 719468;
+    let era = (if days >= 0 { days } else { days - 146096 }) / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted + 2) / 5 + 1) as u64;
+    let month = (if shifted < 10 { shifted + 3 } else { shifted - 9 }) as u64;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
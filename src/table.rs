@@ -1,36 +1,398 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::mem;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use board::Size;
+
+/// Default number of entries, 64 MB for boards of up to 64 bits and twice that
+/// for wider ones. Table sizes are prime, so that an entry's index and partial
+/// key together identify its position (Chinese remainder theorem).
 const TABLE_SIZE: usize = 8388593;
 
-#[derive(Copy, Clone)]
-struct Entry {
-    key: u64,
-    score: i8,
+/// Most entries `load` accepts, 16 GB of one-word entries, so that a corrupt
+/// header cannot ask for an impossible allocation.
+const MAX_ENTRIES: u64 = 1 << 31;
+
+/// Identifies saved tables.
+const MAGIC: &[u8; 4] = b"C4TT";
+
+/// Bumped whenever the meaning of saved scores or the file layout changes.
+const VERSION: u32 = 4;
+
+// Each entry packs, from the lowest bits up: the low bits of the key, the
+// score, the bound, the depth and the age. Keys are unique as long as they
+// are smaller than `entries` shifted by the stored key bits. When one word
+// is not enough for that, a second holds the next 64 bits of the key, XORed
+// with the first so that a torn read by another thread does not match, and
+// the widest boards still need a minimum number of entries; see
+// `min_entries`.
+const KEY_BITS: u32 = 42;
+const SCORE_SHIFT: u32 = KEY_BITS;
+const BOUND_SHIFT: u32 = SCORE_SHIFT + 8;
+const DEPTH_SHIFT: u32 = BOUND_SHIFT + 2;
+const AGE_SHIFT: u32 = DEPTH_SHIFT + 6;
+
+const KEY_MASK: u64 = (1 << KEY_BITS) - 1;
+const DEPTH_MASK: u64 = (1 << 6) - 1;
+const AGE_MASK: u64 = (1 << 6) - 1;
+
+/// What a stored score says about the true score of its position.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Bound {
+    /// The true score is at most the stored score.
+    Upper,
+    /// The true score is at least the stored score.
+    Lower,
+    /// The stored score is the true score.
+    Exact,
 }
 
-pub struct Table { map: Vec<Entry> }
+impl Bound {
+    fn to_bits(self) -> u64 {
+        match self {
+            Bound::Upper => 1,
+            Bound::Lower => 2,
+            Bound::Exact => 3,
+        }
+    }
+
+    /// Zero marks an empty entry.
+    fn from_bits(bits: u64) -> Option<Self> {
+        match bits {
+            1 => Some(Bound::Upper),
+            2 => Some(Bound::Lower),
+            3 => Some(Bound::Exact),
+            _ => None,
+        }
+    }
+}
+
+/// Transposition table that can be shared between threads without locking.
+///
+/// When two positions share an entry, the one with more empty cells left to
+/// search is kept, unless the entry is left over from an earlier search.
+pub struct Table {
+    size: Size,
+    age: AtomicUsize,
+    map: Map,
+}
+
+/// Entries of one word when they keep positions apart, of two otherwise.
+enum Map {
+    Narrow(Vec<AtomicU64>),
+    Wide(Vec<WideEntry>),
+}
+
+trait Entry: Default {
+    /// Key bits kept by the entry besides those given by its index.
+    const STORED_BITS: u32;
+
+    /// The packed word and the check word, which is zero for narrow entries.
+    fn load(&self) -> (u64, u64);
+    fn store(&self, packed: u64, check: u64);
+
+    /// Check word for the packed entry of `key`.
+    fn check(packed: u64, key: u128) -> u64;
+
+    /// Whether the entry holds the position `key`.
+    fn holds(packed: u64, check: u64, key: u128) -> bool {
+        packed & KEY_MASK == key as u64 & KEY_MASK && check == Self::check(packed, key)
+    }
+}
+
+impl Entry for AtomicU64 {
+    const STORED_BITS: u32 = KEY_BITS;
+
+    fn load(&self) -> (u64, u64) {
+        (AtomicU64::load(self, Ordering::Relaxed), 0)
+    }
+
+    fn store(&self, packed: u64, _check: u64) {
+        AtomicU64::store(self, packed, Ordering::Relaxed);
+    }
+
+    fn check(_packed: u64, _key: u128) -> u64 { 0 }
+}
+
+#[derive(Default)]
+struct WideEntry {
+    packed: AtomicU64,
+    check: AtomicU64,
+}
+
+impl Entry for WideEntry {
+    const STORED_BITS: u32 = KEY_BITS + 64;
+
+    fn load(&self) -> (u64, u64) {
+        (self.packed.load(Ordering::Relaxed), self.check.load(Ordering::Relaxed))
+    }
+
+    fn store(&self, packed: u64, check: u64) {
+        self.packed.store(packed, Ordering::Relaxed);
+        self.check.store(check, Ordering::Relaxed);
+    }
+
+    fn check(packed: u64, key: u128) -> u64 {
+        packed ^ (key >> KEY_BITS) as u64
+    }
+}
+
+impl Map {
+    fn len(&self) -> usize {
+        match *self {
+            Map::Narrow(ref map) => map.len(),
+            Map::Wide(ref map) => map.len(),
+        }
+    }
+}
 
 impl Table {
-    pub fn new() -> Self {
-        Table {
-            map: vec![Entry { key: 0, score: 0 }; TABLE_SIZE],
+    pub fn new(size: Size) -> Self {
+        Self::with_entries(size, TABLE_SIZE)
+    }
+
+    /// Creates a table using at most `bytes` of memory, or `min_bytes(size)`
+    /// if that is more.
+    pub fn with_budget(size: Size, bytes: usize) -> Self {
+        let narrow = prime_below(bytes / mem::size_of::<AtomicU64>());
+        let wide = prime_below(bytes / mem::size_of::<WideEntry>());
+        if one_word(size, narrow) {
+            Self::with_entries(size, narrow)
+        } else if wide >= min_entries(size) {
+            Self::with_entries(size, wide)
+        } else {
+            Self::with_entries(size, prime_above(min_entries(size)))
         }
     }
 
-    pub fn index(key: u64) -> usize {
-        key as usize % TABLE_SIZE
+    /// Least memory that keeps every position of `size` apart. Only boards
+    /// of more than `KEY_BITS + 64` bits need more than a few bytes.
+    pub fn min_bytes(size: Size) -> usize {
+        let entries = prime_above(min_entries(size));
+        entries * entry_bytes(size, entries)
     }
 
-    pub fn insert(&mut self, key: u64, score: i8) {
-        let entry = &mut self.map[Self::index(key)];
-        entry.key = key;
-        entry.score = score;
+    /// Memory taken by the entries.
+    pub fn bytes(&self) -> usize {
+        self.map.len() * entry_bytes(self.size, self.map.len())
+    }
+
+    fn with_entries(size: Size, entries: usize) -> Self {
+        let map = if one_word(size, entries) {
+            Map::Narrow((0..entries).map(|_| AtomicU64::default()).collect())
+        } else {
+            Map::Wide((0..entries).map(|_| WideEntry::default()).collect())
+        };
+        Table { size, age: AtomicUsize::new(0), map }
     }
 
-    pub fn get(&mut self, key: u64) -> Option<i8> {
-        let entry = self.map[Self::index(key)];
-        if entry.key == key {
-            Some(entry.score)
+    pub fn index(&self, key: u128) -> usize {
+        // Most boards fit in 64 bits, where division is much cheaper
+        if key >> 64 == 0 {
+            (key as u64 % self.map.len() as u64) as usize
         } else {
-            None
+            (key % self.map.len() as u128) as usize
         }
     }
+
+    /// Empties every entry, keeping the allocation.
+    pub fn clear(&self) {
+        match self.map {
+            Map::Narrow(ref map) => map.iter().for_each(|entry| Entry::store(entry, 0, 0)),
+            Map::Wide(ref map) => map.iter().for_each(|entry| entry.store(0, 0)),
+        }
+    }
+
+    /// Starts a new search, letting its entries replace those of older ones.
+    pub fn next_age(&self) {
+        self.age.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn age(&self) -> u64 {
+        self.age.load(Ordering::Relaxed) as u64 & AGE_MASK
+    }
+
+    /// Stores `score` for the position `key` with `depth` empty cells left.
+    pub fn insert(&self, key: u128, score: i8, bound: Bound, depth: u8) {
+        match self.map {
+            Map::Narrow(ref map) => self.insert_into(map, key, score, bound, depth),
+            Map::Wide(ref map) => self.insert_into(map, key, score, bound, depth),
+        }
+    }
+
+    fn insert_into<E: Entry>(&self, map: &[E], key: u128, score: i8, bound: Bound, depth: u8) {
+        let entry = &map[self.index(key)];
+        let (old, check) = entry.load();
+        let age = self.age();
+
+        let replace = old >> BOUND_SHIFT & 0b11 == 0
+            || E::holds(old, check, key)
+            || old >> AGE_SHIFT & AGE_MASK != age
+            || old >> DEPTH_SHIFT & DEPTH_MASK <= depth as u64;
+
+        if replace {
+            let new = key as u64 & KEY_MASK
+                | (score as u8 as u64) << SCORE_SHIFT
+                | bound.to_bits() << BOUND_SHIFT
+                | (depth as u64).min(DEPTH_MASK) << DEPTH_SHIFT
+                | age << AGE_SHIFT;
+            entry.store(new, E::check(new, key));
+        }
+    }
+
+    pub fn get(&self, key: u128) -> Option<(i8, Bound)> {
+        let entry = match self.map {
+            Map::Narrow(ref map) => Self::probe(map, self.index(key), key)?,
+            Map::Wide(ref map) => Self::probe(map, self.index(key), key)?,
+        };
+        let bound = Bound::from_bits(entry >> BOUND_SHIFT & 0b11)?;
+        Some(((entry >> SCORE_SHIFT) as u8 as i8, bound))
+    }
+
+    fn probe<E: Entry>(map: &[E], index: usize, key: u128) -> Option<u64> {
+        let (entry, check) = map[index].load();
+        if E::holds(entry, check, key) { Some(entry) } else { None }
+    }
+
+    /// Every occupied entry with its index.
+    fn occupied(&self) -> Vec<(u64, u64, u64)> {
+        fn collect<E: Entry>(map: &[E]) -> Vec<(u64, u64, u64)> {
+            map.iter().enumerate().filter_map(|(index, entry)| {
+                let (entry, check) = entry.load();
+                if entry >> BOUND_SHIFT & 0b11 != 0 { Some((index as u64, entry, check)) } else { None }
+            }).collect()
+        }
+        match self.map {
+            Map::Narrow(ref map) => collect(map),
+            Map::Wide(ref map) => collect(map),
+        }
+    }
+
+    fn store(&self, index: usize, entry: u64, check: u64) {
+        match self.map {
+            Map::Narrow(ref map) => Entry::store(&map[index], entry, check),
+            Map::Wide(ref map) => map[index].store(entry, check),
+        }
+    }
+
+    /// Writes every occupied entry to `path`.
+    ///
+    /// The file starts with a header of `MAGIC`, `VERSION`, the board size
+    /// and the number of entries in the table, followed by the number of
+    /// occupied entries and then each of them as its index and two packed
+    /// words, the second of which is zero for tables of one-word entries.
+    /// Integers are little-endian.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let entries = self.occupied();
+
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&VERSION.to_le_bytes())?;
+        file.write_all(&[self.size.cols(), self.size.rows()])?;
+        file.write_all(&(self.map.len() as u64).to_le_bytes())?;
+        file.write_all(&(entries.len() as u64).to_le_bytes())?;
+        for (index, entry, check) in entries {
+            file.write_all(&index.to_le_bytes())?;
+            file.write_all(&entry.to_le_bytes())?;
+            file.write_all(&check.to_le_bytes())?;
+        }
+        file.flush()
+    }
+
+    /// Reads a table written by `save`, rejecting files from another version
+    /// or board size.
+    pub fn load<P: AsRef<Path>>(path: P, size: Size) -> io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+
+        let mut magic = [0; 4];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a transposition table file"))
+        }
+
+        let mut version = [0; 4];
+        file.read_exact(&mut version)?;
+        if u32::from_le_bytes(version) != VERSION {
+            return Err(invalid("unsupported transposition table version"))
+        }
+
+        let mut dimensions = [0; 2];
+        file.read_exact(&mut dimensions)?;
+        if dimensions != [size.cols(), size.rows()] {
+            return Err(invalid("transposition table is for another board size"))
+        }
+
+        let entries = read_u64(&mut file)?;
+        let count = read_u64(&mut file)?;
+        if entries == 0 {
+            return Err(invalid("transposition table has no entries"))
+        }
+        if entries > MAX_ENTRIES {
+            return Err(invalid("transposition table is too large"))
+        }
+        if count > entries {
+            return Err(invalid("transposition table has more occupied entries than entries"))
+        }
+        let entries = entries as usize;
+        if entries < min_entries(size) || (entries & 1 == 0 && size.bits() > stored_bits(size, entries)) {
+            return Err(invalid("transposition table is too small for its board size"))
+        }
+
+        let table = Table::with_entries(size, entries);
+        for _ in 0..count {
+            let index = read_u64(&mut file)? as usize;
+            let entry = read_u64(&mut file)?;
+            let check = read_u64(&mut file)?;
+            if index >= entries {
+                return Err(invalid("transposition table entry out of range"))
+            }
+            table.store(index, entry, check);
+        }
+        Ok(table)
+    }
+}
+
+/// Fewest entries whose index and stored key bits tell every position of
+/// `size` apart, given an odd number of entries.
+fn min_entries(size: Size) -> usize {
+    1 << size.bits().saturating_sub(WideEntry::STORED_BITS)
+}
+
+/// Whether `entries` one-word entries tell every position of `size` apart.
+fn one_word(size: Size, entries: usize) -> bool {
+    size.narrow() && entries >= 1 << size.bits().saturating_sub(AtomicU64::STORED_BITS)
+}
+
+fn stored_bits(size: Size, entries: usize) -> u32 {
+    if one_word(size, entries) { AtomicU64::STORED_BITS } else { WideEntry::STORED_BITS }
+}
+
+fn entry_bytes(size: Size, entries: usize) -> usize {
+    if one_word(size, entries) { mem::size_of::<AtomicU64>() } else { mem::size_of::<WideEntry>() }
+}
+
+fn is_prime(n: usize) -> bool {
+    n >= 2 && (2..).take_while(|d| d * d <= n).all(|d| n % d != 0)
+}
+
+/// Largest prime no greater than `n`, or 2 if there is none.
+fn prime_below(n: usize) -> usize {
+    (2..n + 1).rev().find(|&n| is_prime(n)).unwrap_or(2)
+}
+
+/// Smallest prime no less than `n`.
+fn prime_above(n: usize) -> usize {
+    (n..).find(|&n| is_prime(n)).unwrap()
+}
+
+fn read_u64<R: Read>(file: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    file.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
 }
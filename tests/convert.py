@@ -3,16 +3,23 @@
 from sys import argv
 
 if len(argv) <= 1:
-    print("Usage: ./convert.py <FILE>*")
+    print("Usage: ./convert.py [--exact] <FILE>*")
     exit(-1)
 
-for arg in argv[1:]:
-    output = arg.split('.')[0] + '.dat'
+# Keep exact scores instead of stripping them to -1/0/1
+exact = argv[1] == '--exact'
+files = argv[2:] if exact else argv[1:]
+
+for arg in files:
+    suffix = '-exact.dat' if exact else '.dat'
+    output = arg.split('.')[0] + suffix
     with open(arg, 'r') as infile:
         with open(output, 'w') as outfile:
             for line in infile:
                 case = line.strip().split()
-                if case[1][0] == '-':
+                if exact:
+                    outfile.write(case[0] + ' ' + case[1] + '\n')
+                elif case[1][0] == '-':
                     outfile.write(case[0] + ' -1\n')
                 elif case[1][0] == '0':
                     outfile.write(case[0] + ' 0\n')
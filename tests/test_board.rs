@@ -0,0 +1,185 @@
+extern crate minimax;
+
+use minimax::board::*;
+
+fn play(size: Size, moves: &[u8]) -> Board {
+    let mut board = Board::with_size(size);
+    for &col in moves { board.make_move(col); }
+    board
+}
+
+#[test]
+fn size_limits() {
+    assert!(Size::new(4, 4).is_some());
+    assert!(Size::new(8, 7).is_some());
+    assert!(Size::new(9, 7).is_some());
+    assert!(Size::new(16, 7).is_some());
+    assert!(Size::new(13, 9).is_none());
+    assert!(Size::new(0, 6).is_none());
+    assert_eq!(Size::default(), Size::new(COLS, ROWS).unwrap());
+}
+
+#[test]
+fn sized_wins() {
+    for &(cols, rows) in &[(4, 4), (6, 5), (7, 6), (8, 7), (9, 7), (16, 7)] {
+        let size = Size::new(cols, rows).unwrap();
+        let last = cols - 1;
+
+        let vertical = play(size, &[0, 1, 0, 1, 0, 1, 0]);
+        assert_eq!(vertical.was_won(), Some(WHITE));
+
+        let horizontal = play(size, &[last, last, last - 1, last - 1, last - 2, last - 2, last - 3]);
+        assert_eq!(horizontal.was_won(), Some(WHITE));
+
+        let diagonal = play(size, &[0, 1, 1, 2, 2, 3, 2, 3, 3, 0, 3]);
+        assert_eq!(diagonal.was_won(), Some(WHITE));
+
+        let none = play(size, &[0, 1, 0, 1, 0, 1]);
+        assert_eq!(none.was_won(), None);
+        assert!(none.will_win(0));
+        assert_eq!(none.valid_moves().len(), cols as usize);
+    }
+}
+
+#[test]
+fn tall_sizes() {
+    for &(cols, rows) in &[(1, 63), (1, 127), (2, 42), (3, 20), (4, 24)] {
+        let size = Size::new(cols, rows).unwrap();
+        let mut board = Board::with_size(size);
+        for _ in 0..rows {
+            assert!(!board.will_win(0));
+            board.make_move(0);
+            assert_eq!(board.was_won(), None);
+            assert!(board.mirror().mirror() == board);
+        }
+        assert_eq!(board.valid_moves().len(), cols as usize - 1);
+
+        if cols > 1 {
+            assert!(play(size, &[0, 1, 0, 1, 0, 1]).will_win(0));
+            assert_eq!(play(size, &[0, 1, 0, 1, 0, 1, 0]).was_won(), Some(WHITE));
+        }
+    }
+}
+
+#[test]
+fn sized_full() {
+    let size = Size::new(4, 4).unwrap();
+    let mut board = Board::with_size(size);
+    for _ in 0..4 {
+        board.make_move(0);
+    }
+    assert_eq!(board.valid_moves(), vec![2, 1, 3]);
+}
+
+#[test]
+fn mirrored() {
+    for &(cols, rows) in &[(4, 4), (7, 6), (8, 7), (9, 7), (16, 7)] {
+        let size = Size::new(cols, rows).unwrap();
+        let last = cols - 1;
+        let board = play(size, &[0, 1, 1, 2, 0]);
+        let mirror = play(size, &[last, last - 1, last - 1, last - 2, last]);
+
+        assert!(board.mirror() == mirror);
+        assert!(board.mirror().mirror() == board);
+        assert_eq!(board.canonical_key(), mirror.canonical_key());
+        assert!(board.canonical_key() <= board.key());
+        for row in 0..rows {
+            for col in 0..cols {
+                assert_eq!(board.get(row, col), mirror.get(row, last - col));
+            }
+        }
+    }
+
+    let center = play(Size::default(), &[3, 3, 3]);
+    assert_eq!(center.canonical_key(), center.key());
+}
+
+#[test]
+fn parse_errors() {
+    let error = |index, kind| Err(ParseError { index, kind });
+    let size = Size::default();
+    assert_eq!(Board::parse(size, "44x"), error(2, ParseErrorKind::InvalidChar('x')));
+    assert_eq!(Board::parse(size, "408"), error(1, ParseErrorKind::OutOfRange(0)));
+    assert_eq!(Board::parse(size, "48"), error(1, ParseErrorKind::OutOfRange(8)));
+    assert_eq!(Board::parse(size, "4444444"), error(6, ParseErrorKind::ColumnFull(4)));
+    assert_eq!(Board::parse(size, "12121211"), error(7, ParseErrorKind::GameOver));
+    assert_eq!(Board::parse(Size::new(4, 4).unwrap(), "5"), error(0, ParseErrorKind::OutOfRange(5)));
+    assert_eq!("4453".parse::<Board>(), Ok(Board::from("4453")));
+    assert_eq!("".parse::<Board>(), Ok(Board::new()));
+}
+
+#[test]
+fn move_string_round_trip() {
+    for &moves in &["", "4", "4453", "1212121", "444444355", "2252576253462244111563365343671351441"] {
+        let board = Board::from(moves);
+        let replayed = Board::from(&board.to_move_string().unwrap());
+        assert_eq!(replayed, board);
+    }
+
+    let size = Size::new(5, 4).unwrap();
+    let board = Board::parse(size, "3322114").unwrap();
+    assert_eq!(Board::parse(size, &board.to_move_string().unwrap()), Ok(board));
+
+    // Two finished lines cannot both have been completed in one game.
+    let mut board = Board::new();
+    board.all = 0b1111 | 0b1111 << 7;
+    board.owned = 0b1111;
+    board.moves = 8;
+    assert_eq!(board.to_move_string(), None);
+
+    // No white stone could have been played first, which only shows once
+    // every other stone has been taken back.
+    let board = Board::from_fen("WW2WW1/BBB1BB1/WWW1WW1/BBB1BBW/WWW1WWW/BBB1BBB").unwrap();
+    assert_eq!(board.to_move_string(), None);
+}
+
+#[test]
+fn checked_play() {
+    let mut board = Board::new();
+    assert_eq!(board.play(7), Err(MoveError::OutOfRange(7)));
+    for _ in 0..5 { assert_eq!(board.play(3), Ok(MoveOutcome::Ongoing)); }
+    assert_eq!(board.play(3), Ok(MoveOutcome::Ongoing));
+    assert_eq!(board.play(3), Err(MoveError::ColumnFull(3)));
+    assert_eq!(board, Board::from("444444"));
+
+    let mut board = Board::from("121212");
+    assert_eq!(board.play(0), Ok(MoveOutcome::Won));
+    assert_eq!(board.play(2), Err(MoveError::GameOver));
+    assert_eq!(board.take_back(1), Err(MoveError::NotTakeBack(1)));
+    assert_eq!(board.take_back(2), Err(MoveError::NotTakeBack(2)));
+    assert_eq!(board.take_back(0), Ok(()));
+    assert_eq!(board, Board::from("121212"));
+
+    let size = Size::new(4, 1).unwrap();
+    let mut board = Board::parse(size, "123").unwrap();
+    assert_eq!(board.play(3), Ok(MoveOutcome::Drawn));
+}
+
+#[test]
+fn grid_round_trip() {
+    for &moves in &["", "4", "4453", "1212121", "2252576253462244111563365343671351441"] {
+        let board = Board::from(moves);
+        assert_eq!(Board::from_grid(&board.to_grid()), Ok(board.clone()));
+        assert_eq!(Board::from_grid(&board.to_string()), Ok(board.clone()));
+        assert_eq!(Board::from_fen(&board.to_fen()), Ok(board));
+    }
+
+    let board = Board::parse(Size::new(5, 4).unwrap(), "3322114").unwrap();
+    assert_eq!(board.to_fen(), "5/5/BBB2/WWWW1");
+    assert_eq!(Board::from_fen("5/5/BBB2/WWWW1"), Ok(board.clone()));
+    assert_eq!(Board::from_grid(". . . . .\n. . . . .\nB B B . .\nW W W W .\n"), Ok(board));
+    assert_eq!(Board::from_fen("7/7/7/7/7/3W3"), Ok(Board::from("4")));
+}
+
+#[test]
+fn grid_errors() {
+    assert_eq!(Board::from_fen("4/4/4/3X"), Err(GridError::InvalidChar('X')));
+    assert_eq!(Board::from_fen("4/4/5/4"), Err(GridError::Ragged));
+    assert_eq!(Board::from_fen("13/13/13/13/13/13/13/13/13"), Err(GridError::Size(13, 9)));
+    assert_eq!(Board::from_fen("4/4/W3/4"), Err(GridError::Floating(1, 0)));
+    assert_eq!(Board::from_fen("4/4/4/B3"), Err(GridError::Count));
+    assert_eq!(Board::from_fen("4/4/4/WW2"), Err(GridError::Count));
+    assert_eq!(Board::from_fen("4/BBBB/WWWW/BWBW"), Err(GridError::Won));
+    assert_eq!(Board::from_fen("B3/BW2/BW2/BWWW"), Err(GridError::Won));
+    assert_eq!(Board::from_grid("W . \n. ."), Err(GridError::Floating(1, 0)));
+}
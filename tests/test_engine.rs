@@ -0,0 +1,448 @@
+extern crate minimax;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+use minimax::board::*;
+use minimax::engine::*;
+use minimax::minimax::AI;
+use minimax::record::*;
+
+/// Plays a fixed sequence of turns, each taking its delay.
+struct Script {
+    turns: VecDeque<(Turn, Duration)>,
+    interactive: bool,
+}
+
+fn script(turns: &[Turn], interactive: bool) -> Script {
+    let turns = turns.iter().map(|&turn| (turn, Duration::from_secs(0))).collect();
+    Script { turns, interactive }
+}
+
+impl Script {
+    /// Adds a turn that takes `millis`.
+    fn then(mut self, turn: Turn, millis: u64) -> Self {
+        self.turns.push_back((turn, Duration::from_millis(millis)));
+        self
+    }
+}
+
+impl Player for Script {
+    fn take_turn(&mut self, _: &Board, _: Option<TimeLeft>) -> Turn {
+        let (turn, delay) = self.turns.pop_front().expect("script ran out of turns");
+        thread::sleep(delay);
+        turn
+    }
+
+    fn is_interactive(&self) -> bool { self.interactive }
+}
+
+#[test]
+fn undo_redo() {
+    use Turn::*;
+    let white = script(&[Move(0), Undo, Redo, Undo, Move(3), Move(3), Move(3), Move(3)], true);
+    let black = script(&[Move(6); 4], false);
+    let record = Engine::new(Size::default(), white, black).run().record;
+    assert_eq!(record.moves, vec![3, 6, 3, 6, 3, 6, 3]);
+    assert_eq!(record.outcome, Outcome::WhiteWon);
+}
+
+#[test]
+fn undo_between_people() {
+    use Turn::*;
+    let white = script(&[Move(0), Move(2), Move(2), Move(2), Move(2)], true);
+    let black = script(&[Undo, Move(5), Move(5), Move(5)], true);
+    let record = Engine::new(Size::default(), white, black).run().record;
+    assert_eq!(record.moves, vec![2, 5, 2, 5, 2, 5, 2]);
+}
+
+#[test]
+fn restart() {
+    use Turn::*;
+    let white = script(&[Move(0), Restart, Move(1), Move(1), Move(1), Move(1)], true);
+    let black = script(&[Move(0), Move(4), Move(4), Move(4)], false);
+    let record = Engine::new(Size::default(), white, black).run().record;
+    assert_eq!(record.moves, vec![1, 4, 1, 4, 1, 4, 1]);
+}
+
+// Timed tests leave at least ten times the time a turn should take, since
+// sleeping never takes less than asked but may take much more.
+
+#[test]
+fn lost_on_time() {
+    use Turn::*;
+    let white = script(&[Move(0); 2], false).then(Move(0), 1000);
+    let black = script(&[Move(1); 3], false);
+    let record = Engine::new(Size::default(), white, black)
+        .with_time_control(TimeControl::SuddenDeath(Duration::from_millis(100)))
+        .run().record;
+    assert_eq!(record.moves, vec![0, 1, 0, 1]);
+    assert_eq!(record.outcome, Outcome::BlackWon);
+    assert_eq!(record.termination, Termination::Time);
+    assert!(record.times[0].unwrap() < 100);
+}
+
+/// Plays a script, noting the time it has left on each turn.
+struct Clocked {
+    script: Script,
+    left: Rc<RefCell<Vec<Duration>>>,
+}
+
+impl Player for Clocked {
+    fn take_turn(&mut self, board: &Board, time: Option<TimeLeft>) -> Turn {
+        self.left.borrow_mut().push(time.unwrap().remaining);
+        self.script.take_turn(board, time)
+    }
+}
+
+#[test]
+fn time_controls() {
+    use Turn::*;
+    let second = Duration::from_secs(1);
+    let controls = [
+        TimeControl::PerMove(second),
+        TimeControl::SuddenDeath(second),
+        TimeControl::Increment { base: second, increment: second },
+    ];
+    for &control in &controls {
+        let left = Rc::new(RefCell::new(Vec::new()));
+        let white = Clocked { script: script(&[Move(0); 4], false), left: left.clone() };
+        let black = script(&[Move(1); 3], false);
+        let record = Engine::new(Size::default(), white, black).with_time_control(control).run().record;
+        assert_eq!(record.outcome, Outcome::WhiteWon);
+        assert_eq!(record.termination, Termination::Normal);
+
+        for (turn, &left) in left.borrow().iter().enumerate() {
+            let full = match control {
+                TimeControl::Increment { .. } => second * (turn as u32 + 1),
+                _ => second,
+            };
+            assert!(left <= full && left + Duration::from_millis(100) > full);
+        }
+    }
+}
+
+#[test]
+fn time_between_moves() {
+    use Turn::*;
+    let second = Duration::from_secs(1);
+    let controls = [
+        TimeControl::PerMove(second),
+        TimeControl::Increment { base: second, increment: second },
+    ];
+    for &control in &controls {
+        let left = Rc::new(RefCell::new(Vec::new()));
+        let turns = script(&[Move(0)], false)
+            .then(Redo, 20).then(OfferDraw, 20).then(Redo, 20).then(Redo, 20).then(Resign, 0);
+        let white = Clocked { script: turns, left: left.clone() };
+        let black = script(&[Move(1)], false);
+        let record = Engine::new(Size::default(), white, black).with_time_control(control).run().record;
+        assert_eq!(record.termination, Termination::Resignation);
+
+        // Turns that do not move only use up time
+        let left = left.borrow();
+        assert_eq!(left.len(), 6);
+        assert!(left[1..].windows(2).all(|pair| pair[1] < pair[0]));
+    }
+}
+
+#[test]
+fn time_budget() {
+    let board = Board::new();
+    let per_move = TimeLeft { remaining: Duration::from_secs(1), control: TimeControl::PerMove(Duration::from_secs(1)) };
+    assert_eq!(per_move.budget(&board), Duration::from_millis(800));
+
+    let game = TimeControl::SuddenDeath(Duration::from_secs(21));
+    let sudden_death = TimeLeft { remaining: Duration::from_secs(21), control: game };
+    assert_eq!(sudden_death.budget(&board), Duration::from_millis(800));
+
+    let game = TimeControl::Increment { base: Duration::from_secs(21), increment: Duration::from_secs(1) };
+    let increment = TimeLeft { remaining: Duration::from_secs(21), control: game };
+    assert_eq!(increment.budget(&board), Duration::from_millis(1600));
+}
+
+#[test]
+fn timed_cpu() {
+    // Small enough to solve in far less than the time allowed
+    let control = TimeControl::PerMove(Duration::from_secs(1));
+    let record = cpu_vs_cpu(Size::new(5, 4).unwrap()).with_time_control(control).run().record;
+    assert_eq!(record.termination, Termination::Normal);
+    assert!(record.times.iter().all(|time| time.unwrap() < 1000));
+}
+
+/// Plays a script, logging every call it receives.
+struct Logger {
+    script: Script,
+    accept: bool,
+    log: Rc<RefCell<Vec<String>>>,
+}
+
+impl Player for Logger {
+    fn new_game(&mut self, size: Size, color: i8) {
+        self.log.borrow_mut().push(format!("new {}x{} {}", size.cols(), size.rows(), color));
+    }
+
+    fn take_turn(&mut self, board: &Board, time: Option<TimeLeft>) -> Turn {
+        self.log.borrow_mut().push(format!("turn {}", board.moves));
+        self.script.take_turn(board, time)
+    }
+
+    fn opponent_moved(&mut self, board: &Board, col: u8) {
+        self.log.borrow_mut().push(format!("moved {} {}", col, board.moves));
+    }
+
+    fn history_changed(&mut self, board: &Board) {
+        self.log.borrow_mut().push(format!("history {}", board.moves));
+    }
+
+    fn accept_draw(&mut self, _: &Board) -> bool {
+        self.log.borrow_mut().push("draw?".to_string());
+        self.accept
+    }
+
+    fn game_over(&mut self, record: &GameRecord) {
+        self.log.borrow_mut().push(format!("over {:?}", record.outcome));
+    }
+}
+
+fn logger(turns: &[Turn], accept: bool) -> (Logger, Rc<RefCell<Vec<String>>>) {
+    interactive_logger(turns, accept, false)
+}
+
+fn interactive_logger(turns: &[Turn], accept: bool, interactive: bool) -> (Logger, Rc<RefCell<Vec<String>>>) {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    (Logger { script: script(turns, interactive), accept, log: log.clone() }, log)
+}
+
+#[test]
+fn lifecycle_hooks() {
+    use Turn::*;
+    let (white, white_log) = logger(&[Move(3), Restart, Move(2), Resign], false);
+    let (black, black_log) = logger(&[Move(3), Move(2)], false);
+    let record = Engine::new(Size::new(5, 4).unwrap(), white, black).run().record;
+    assert_eq!(record.moves, vec![2, 2]);
+    assert_eq!(record.outcome, Outcome::BlackWon);
+    assert_eq!(record.termination, Termination::Resignation);
+    assert_eq!(*white_log.borrow(), vec![
+        "new 5x4 0", "turn 0", "moved 3 2", "turn 2", "new 5x4 0", "history 0", "turn 0", "moved 2 2",
+        "turn 2", "over BlackWon",
+    ]);
+    assert_eq!(*black_log.borrow(), vec![
+        "new 5x4 1", "moved 3 1", "turn 1", "new 5x4 1", "history 0", "moved 2 1", "turn 1",
+        "over BlackWon",
+    ]);
+}
+
+#[test]
+fn history_hooks() {
+    use Turn::*;
+    let (white, white_log) = interactive_logger(&[Move(0), Undo, Redo, Resign], false, true);
+    let (black, black_log) = logger(&[Move(1), Move(1)], false);
+    let record = Engine::new(Size::default(), white, black).run().record;
+    assert_eq!(record.moves, vec![0, 1]);
+    assert_eq!(*white_log.borrow(), vec![
+        "new 7x6 0", "turn 0", "moved 1 2", "turn 2", "history 0", "turn 0", "history 2", "turn 2",
+        "over BlackWon",
+    ]);
+    assert_eq!(*black_log.borrow(), vec![
+        "new 7x6 1", "moved 0 1", "turn 1", "history 0", "history 2", "over BlackWon",
+    ]);
+}
+
+#[test]
+fn draw_offers() {
+    use Turn::*;
+    let white = script(&[Move(0), OfferDraw, Move(0), OfferDraw, Resign], false);
+    let (black, log) = logger(&[Move(1), Move(1)], false);
+    let (other, _) = logger(&[Move(1)], true);
+
+    let record = Engine::new(Size::default(), white, black).run().record;
+    assert_eq!(record.moves, vec![0, 1, 0, 1]);
+    assert_eq!(log.borrow().iter().filter(|line| *line == "draw?").count(), 2);
+    assert_eq!(record.outcome, Outcome::BlackWon);
+
+    let white = script(&[Move(0), OfferDraw], false);
+    let record = Engine::new(Size::default(), white, other).run().record;
+    assert_eq!(record.moves, vec![0, 1]);
+    assert_eq!(record.outcome, Outcome::Draw);
+    assert_eq!(record.termination, Termination::Agreement);
+}
+
+#[test]
+fn repeated_draw_offers() {
+    use Turn::*;
+    let white = script(&[OfferDraw, OfferDraw, OfferDraw, Move(0), OfferDraw, Resign], true);
+    let (black, log) = logger(&[Move(1)], false);
+    let record = Engine::new(Size::default(), white, black).with_observer(Silent).run().record;
+    assert_eq!(record.moves, vec![0, 1]);
+    assert_eq!(record.termination, Termination::Resignation);
+    assert_eq!(log.borrow().iter().filter(|line| *line == "draw?").count(), 2);
+
+    // A bot is asked once more after repeating an offer, then forfeits
+    let white = script(&[OfferDraw, OfferDraw, Move(0), OfferDraw, OfferDraw, OfferDraw], false);
+    let (black, log) = logger(&[Move(1)], false);
+    let record = Engine::new(Size::default(), white, black).with_observer(Silent).run().record;
+    assert_eq!(record.moves, vec![0, 1]);
+    assert_eq!((record.outcome, record.termination), (Outcome::BlackWon, Termination::Forfeit));
+    assert_eq!(log.borrow().iter().filter(|line| *line == "draw?").count(), 2);
+}
+
+#[test]
+fn always_offering_draws() {
+    struct Pest;
+
+    impl Player for Pest {
+        fn take_turn(&mut self, _: &Board, _: Option<TimeLeft>) -> Turn { Turn::OfferDraw }
+    }
+
+    // Without a clock, only the forfeit ends the game
+    let (black, log) = logger(&[], false);
+    let summary = Engine::new(Size::default(), Pest, black).with_observer(Silent).run();
+    assert_eq!((summary.winner, summary.termination), (Some(BLACK), Termination::Forfeit));
+    assert_eq!(log.borrow().iter().filter(|line| *line == "draw?").count(), 1);
+}
+
+/// Counts what it is told.
+#[derive(Default)]
+struct Tally {
+    positions: usize,
+    moves: Vec<(i8, u8)>,
+    offers: Vec<(i8, bool)>,
+    winner: Option<Option<i8>>,
+}
+
+struct Watch(Rc<RefCell<Tally>>);
+
+impl Observer for Watch {
+    fn position(&mut self, _: &Board) {
+        self.0.borrow_mut().positions += 1;
+    }
+
+    fn moved(&mut self, color: i8, col: u8) {
+        self.0.borrow_mut().moves.push((color, col));
+    }
+
+    fn draw_offered(&mut self, color: i8, accepted: bool) {
+        self.0.borrow_mut().offers.push((color, accepted));
+    }
+
+    fn game_over(&mut self, summary: &Summary) {
+        self.0.borrow_mut().winner = Some(summary.winner);
+    }
+}
+
+#[test]
+fn summary() {
+    use Turn::*;
+    let tally = Rc::new(RefCell::new(Tally::default()));
+    let white = script(&[Move(2), Move(2), Move(2), Move(2)], false);
+    let black = script(&[OfferDraw, Move(3), Move(3), Move(3)], false);
+    let summary = Engine::new(Size::default(), white, black).with_observer(Watch(tally.clone())).run();
+
+    assert_eq!(summary.winner, Some(WHITE));
+    assert_eq!(summary.termination, Termination::Normal);
+    assert_eq!(summary.board, Board::from("3434343"));
+    assert_eq!(summary.moves(), &[2, 3, 2, 3, 2, 3, 2]);
+
+    let tally = tally.borrow();
+    assert_eq!(tally.positions, 9);
+    assert_eq!(tally.moves, vec![(0, 2), (1, 3), (0, 2), (1, 3), (0, 2), (1, 3), (0, 2)]);
+    assert_eq!(tally.offers, vec![(1, false)]);
+    assert_eq!(tally.winner, Some(Some(WHITE)));
+
+    let white = script(&[Resign], false);
+    let summary = Engine::new(Size::default(), white, script(&[], false)).with_observer(Silent).run();
+    assert_eq!(summary.winner, Some(BLACK));
+    assert_eq!(summary.termination, Termination::Resignation);
+    assert_eq!(summary.board, Board::new());
+}
+
+#[test]
+fn builder() {
+    use Turn::*;
+    let white = script(&[Move(0), Resign], false);
+    let summary = EngineBuilder::new()
+        .position(Board::from("2233"))
+        .player(WHITE, "Script", Box::new(white))
+        .player(BLACK, "Computer", Box::new(AI::new()))
+        .observer(Silent)
+        .build()
+        .run();
+
+    // Black has to block the open three
+    assert_eq!(summary.moves(), &[0, 3]);
+    assert_eq!(summary.record.start, Some(Board::from("2233")));
+    assert_eq!(summary.record.white, "Script");
+    assert_eq!(summary.record.black, "Computer");
+    assert_eq!(summary.board, Board::from("223314"));
+    assert_eq!(summary.winner, Some(BLACK));
+
+    let text = summary.record.to_string();
+    assert!(text.contains("[FEN \"7/7/7/7/1BB4/1WW4\"]"));
+    assert_eq!(text.parse::<GameRecord>(), Ok(summary.record));
+}
+
+#[test]
+#[should_panic(expected = "player color must be WHITE or BLACK")]
+fn builder_color() {
+    EngineBuilder::new().player(2, "Nobody", Box::new(AI::new()));
+}
+
+#[test]
+fn illegal_moves() {
+    use Turn::*;
+    // Out of range, then into the column the first six moves filled.
+    for &col in &[7, 0] {
+        let white = script(&[Move(0), Move(0), Move(0), Move(col)], false);
+        let black = script(&[Move(0); 3], false);
+        let summary = Engine::new(Size::default(), white, black).with_observer(Silent).run();
+        assert_eq!((summary.winner, summary.termination), (Some(BLACK), Termination::IllegalMove));
+        assert_eq!(summary.moves(), &[0; 6]);
+        assert_eq!(summary.record.to_string().parse::<GameRecord>(), Ok(summary.record.clone()));
+    }
+}
+
+#[test]
+fn builder_restart() {
+    use Turn::*;
+    let black = script(&[Move(4), Restart, Move(0), Move(0), Move(0), Move(0)], true);
+    let white = script(&[Move(6); 4], false);
+    let summary = EngineBuilder::new()
+        .position(Board::from("2"))
+        .player(BLACK, "Black", Box::new(black))
+        .player(WHITE, "White", Box::new(white))
+        .time_control(TimeControl::PerMove(Duration::from_secs(60)))
+        .observer(Silent)
+        .build()
+        .run();
+    assert_eq!(summary.moves(), &[0, 6, 0, 6, 0, 6, 0]);
+    assert_eq!(summary.winner, Some(BLACK));
+}
+
+#[test]
+fn human_input_closed() {
+    use std::process::{Command, Stdio};
+    use std::time::Instant;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_c4"))
+        .args(["play", "--black", "random", "5", "4"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // Prompting again at end of input would never finish
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() { break status }
+        if start.elapsed() > Duration::from_secs(10) {
+            child.kill().unwrap();
+            panic!("c4 play kept reading closed input");
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    assert!(status.success());
+}
@@ -0,0 +1,112 @@
+extern crate minimax;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+
+use std::time::Duration;
+
+use minimax::board::*;
+use minimax::record::*;
+
+#[test]
+fn recorded_game() {
+    let mut record = GameRecord::new(Size::default(), "Alice", "Bob");
+    for &col in &[0, 1, 0, 1, 0, 1] {
+        assert_eq!(record.push(col), Ok(MoveOutcome::Ongoing));
+    }
+    assert_eq!(record.outcome, Outcome::Ongoing);
+    assert_eq!(record.finished, None);
+
+    assert_eq!(record.push(7), Err(MoveError::OutOfRange(7)));
+    assert_eq!(record.push(0), Ok(MoveOutcome::Won));
+    assert_eq!(record.push(2), Err(MoveError::GameOver));
+    assert_eq!(record.moves, vec![0, 1, 0, 1, 0, 1, 0]);
+    assert_eq!(record.outcome, Outcome::WhiteWon);
+    assert!(record.finished.unwrap() >= record.started);
+    assert_eq!(record.board(), Ok(Board::from("1212121")));
+}
+
+#[test]
+fn recorded_draw() {
+    let mut record = GameRecord::new(Size::new(4, 1).unwrap(), "White", "Black");
+    for &col in &[0, 1, 2] { record.push(col).unwrap(); }
+    assert_eq!(record.push(3), Ok(MoveOutcome::Drawn));
+    assert_eq!(record.outcome, Outcome::Draw);
+}
+
+#[test]
+fn record_text() {
+    let mut record = GameRecord::new(Size::new(5, 4).unwrap(), "Alice \"A\" \\", "Bob");
+    record.started = 1528329600;
+    for &col in &[2, 2, 1, 1, 0] {
+        record.push_timed(col, Duration::from_millis(1250)).unwrap();
+    }
+    record.push(0).unwrap();
+    record.push_timed(3, Duration::from_secs(2)).unwrap();
+    record.finished = Some(1528329930);
+
+    let text = record.to_string();
+    assert_eq!(text, "\
+[White \"Alice \\\"A\\\" \\\\\"]
+[Black \"Bob\"]
+[Date \"2018.06.07 00:00:00\"]
+[EndDate \"2018.06.07 00:05:30\"]
+[Size \"5x4\"]
+[Result \"1-0\"]
+[Termination \"normal\"]
+
+1. 3 {1.250} 3 {1.250} 2. 2 {1.250} 2 {1.250} 3. 1 {1.250} 1 4. 4 {2.000} 1-0
+");
+    assert_eq!(text.parse::<GameRecord>(), Ok(record.clone()));
+
+    let mut resigned = GameRecord::new(Size::default(), "Carol", "Dave");
+    resigned.push(3).unwrap();
+    resigned.finish(Outcome::WhiteWon, Termination::Resignation);
+    let ongoing = GameRecord::new(Size::default(), "Erin", "Frank");
+    let archive = format!("{}\n{}\n{}", record, resigned, ongoing);
+    assert_eq!(GameRecord::parse_all(&archive), Ok(vec![record, resigned, ongoing]));
+}
+
+#[test]
+fn record_errors() {
+    let header = "[White \"A\"]\n[Black \"B\"]\n[Date \"2018.06.07 00:00:00\"]\n[Size \"7x6\"]\n";
+    let parse = |moves: &str| format!("{}[Result \"*\"]\n\n{}", header, moves).parse::<GameRecord>();
+    assert_eq!(parse("1. 4 4 *").map(|game| game.moves), Ok(vec![3, 3]));
+    assert_eq!(parse("1. 4 8 *"), Err(RecordError::Move(1, MoveError::OutOfRange(7))));
+    assert_eq!(parse("1. 4 x *"), Err(RecordError::Line(7)));
+    assert_eq!(parse("1. 4 4"), Err(RecordError::Unterminated));
+    assert_eq!(parse("1. 4 4 1-0"), Err(RecordError::Line(7)));
+    assert_eq!(parse("1. 1 2 2. 1 2 3. 1 2 4. 1 *"), Err(RecordError::Result));
+
+    let game = format!("{}[Result \"*\"]\n\n*", header.replace("7x6", "13x9"));
+    assert_eq!(game.parse::<GameRecord>(), Err(RecordError::InvalidTag("Size".to_string())));
+    let game = format!("{}\n*", header);
+    assert_eq!(game.parse::<GameRecord>(), Err(RecordError::MissingTag("Result")));
+
+    let dated = |date: &str| format!("{}[Result \"*\"]\n\n*", header.replace("2018.06.07", date));
+    for &date in &["2024.02.31", "2023.02.29", "2018.04.31", "2100.02.29"] {
+        assert_eq!(dated(date).parse::<GameRecord>(), Err(RecordError::InvalidTag("Date".to_string())));
+    }
+    for &date in &["2024.02.29", "2000.02.29", "2018.12.31"] {
+        assert!(dated(date).parse::<GameRecord>().is_ok());
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serialized() {
+    let board = Board::from("4453");
+    let json = serde_json::to_string(&board).unwrap();
+    assert_eq!(json, "\"7/7/7/7/3B3/2BWW2\"");
+    assert_eq!(serde_json::from_str::<Board>(&json).unwrap(), board);
+    assert!(serde_json::from_str::<Board>("\"7/7/7/7/7/BB5\"").is_err());
+
+    let mut record = GameRecord::new(Size::new(5, 4).unwrap(), "Alice", "Bob");
+    for &col in &[2, 2, 1, 1, 0, 0, 3] { record.push(col).unwrap(); }
+    let json = serde_json::to_string(&record).unwrap();
+    assert!(json.contains("\"size\":{\"cols\":5,\"rows\":4}"));
+    assert!(json.contains("\"outcome\":\"WhiteWon\""));
+    assert_eq!(serde_json::from_str::<GameRecord>(&json).unwrap(), record);
+
+    let json = json.replace("\"cols\":5", "\"cols\":30");
+    assert!(serde_json::from_str::<GameRecord>(&json).is_err());
+}
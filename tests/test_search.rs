@@ -1,13 +1,16 @@
 extern crate minimax;
 
+use std::env;
 use std::io::*;
-use std::fs::File;
+use std::fs::{self, File};
 use std::str::FromStr;
 use std::time::{Instant, Duration};
 use minimax::board::*;
 use minimax::minimax::*;
+use minimax::opening;
 
 const END_EASY: &'static str = "end-easy.dat";
+const END_EASY_EXACT: &'static str = "end-easy-exact.dat";
 const MIDDLE_EASY: &'static str = "middle-easy.dat";
 const BEGIN_EASY: &'static str = "begin-easy.dat";
 const MIDDLE_MEDIUM: &'static str = "middle-medium.dat";
@@ -53,16 +56,34 @@ fn analyze(times: Vec<f64>) -> (f64, f64) {
     (mean, std)
 }
 
-fn run_test(file: &'static str) {
+fn weak(ai: &mut AI, board: &mut Board) -> i8 {
+    ai.negamax(board, -1, 1)
+}
+
+fn strong(ai: &mut AI, board: &mut Board) -> i8 {
+    ai.score(board)
+}
+
+fn exact(guess: i8, score: i8) -> bool {
+    guess == score
+}
+
+fn same_sign(guess: i8, score: i8) -> bool {
+    guess.signum() == score
+}
+
+/// Scores every case of `file` with `solve`, printing how many `check`
+/// accepts and how long they took. Returns whether all were accepted.
+fn run_test(file: &'static str, solve: fn(&mut AI, &mut Board) -> i8, check: fn(i8, i8) -> bool) -> bool {
     let mut ai = AI::new();
     let mut total = 0;
     let mut correct = 0;
     let mut times = Vec::new();
     for mut case in parse(file) {
         let start = Instant::now();
-        let guess = ai.negamax(&mut case.board, -1, 1);
+        let guess = solve(&mut ai, &mut case.board);
         let stop = Instant::now();
-        if guess == case.score { correct += 1; }
+        if check(guess, case.score) { correct += 1; }
         times.push(elapsed(stop - start));
         total += 1;
     }
@@ -71,34 +92,392 @@ fn run_test(file: &'static str) {
     println!("Correctness: {}/{}", correct, total);
     println!("Mean search time: {}", mean);
     println!("Standard deviation: {}", std);
+    correct == total
+}
+
+fn run_parallel_test(file: &'static str, threads: usize) {
+    let mut ai = AI::new();
+    ai.set_threads(threads);
+    for mut case in parse(file) {
+        assert_eq!(ai.negamax(&mut case.board, -1, 1), case.score.signum());
+    }
 }
 
 #[test]
 fn end_easy_null() {
-    run_test(END_EASY);
+    run_test(END_EASY, weak, exact);
 }
 
 #[test]
 fn middle_easy_null() {
-    run_test(MIDDLE_EASY);
+    run_test(MIDDLE_EASY, weak, exact);
 }
 
 #[test]
 fn middle_medium_null() {
-    run_test(MIDDLE_MEDIUM);
+    run_test(MIDDLE_MEDIUM, weak, exact);
 }
 
 #[test]
 fn begin_easy_null() {
-    run_test(BEGIN_EASY);
+    run_test(BEGIN_EASY, weak, exact);
 }
 
 #[test]
 fn begin_medium_null() {
-    run_test(BEGIN_MEDIUM);
+    run_test(BEGIN_MEDIUM, weak, exact);
 }
 
 #[test]
 fn begin_hard_null() {
-    run_test(BEGIN_HARD);
+    run_test(BEGIN_HARD, weak, exact);
+}
+
+#[test]
+fn middle_medium_parallel() {
+    run_parallel_test(MIDDLE_MEDIUM, 4);
+}
+
+#[test]
+fn begin_easy_parallel() {
+    run_parallel_test(BEGIN_EASY, 4);
+}
+
+#[test]
+fn begin_medium_parallel() {
+    run_parallel_test(BEGIN_MEDIUM, 4);
+}
+
+#[test]
+fn end_easy_parallel_strong() {
+    let mut ai = AI::new();
+    ai.set_threads(4);
+    for mut case in parse(END_EASY_EXACT) {
+        assert_eq!(ai.score(&mut case.board), case.score);
+    }
+}
+
+#[test]
+fn end_easy_strong() {
+    assert!(run_test(END_EASY, strong, same_sign));
+}
+
+#[test]
+fn end_easy_exact_strong() {
+    assert!(run_test(END_EASY_EXACT, strong, exact));
+}
+
+#[test]
+fn middle_easy_strong() {
+    assert!(run_test(MIDDLE_EASY, strong, same_sign));
+}
+
+#[test]
+fn middle_medium_strong() {
+    assert!(run_test(MIDDLE_MEDIUM, strong, same_sign));
+}
+
+#[test]
+fn immediate_win_strong() {
+    let mut ai = AI::new();
+    let mut board = Board::from("112233");
+    assert_eq!(ai.score(&mut board), (SIZE + 1 - 6) / 2);
+}
+
+#[test]
+fn double_threat_strong() {
+    let mut ai = AI::new();
+    let mut board = Board::from("3344");
+    assert_eq!(ai.score(&mut board), (SIZE + 1 - 6) / 2);
+}
+
+#[test]
+fn double_threat_variation() {
+    let mut ai = AI::new();
+    let board = Board::from("3344");
+    let line = ai.principal_variation(&board);
+    assert_eq!(line.len(), 3);
+
+    let mut board = board.clone();
+    for col in line { board.make_move(col); }
+    assert_eq!(board.was_won(), Some(WHITE));
+}
+
+#[test]
+fn end_easy_variation() {
+    let mut ai = AI::new();
+    for mut case in parse(END_EASY).into_iter().take(100) {
+        let score = ai.score(&mut case.board);
+        let line = ai.principal_variation(&case.board);
+        let mover = case.board.moves & 1;
+        for col in line { case.board.make_move(col); }
+        match case.board.was_won() {
+            None => assert_eq!(score, 0),
+            Some(color) => {
+                assert_eq!(score > 0, color == mover);
+                assert_eq!(score.abs(), (SIZE + 2 - case.board.moves) / 2);
+            }
+        }
+    }
+}
+
+#[test]
+fn end_easy_solve() {
+    let mut ai = AI::new();
+    for mut case in parse(END_EASY).into_iter().filter(|case| case.score != 0).take(100) {
+        let col = ai.solve(&mut case.board).unwrap();
+        case.board.make_move(col);
+        let won = case.board.was_won().is_some();
+        assert!(won || ai.negamax(&mut case.board, -1, 1) == -case.score.signum());
+    }
+}
+
+#[test]
+fn middle_easy_solve_past_book() {
+    let mut ai = AI::new();
+    ai.set_book_miss(BookMiss::Think(Limits { depth: Some(1), time: None }));
+    for mut case in parse(MIDDLE_EASY).into_iter().filter(|case| case.score != 0).take(200) {
+        let col = ai.solve(&mut case.board).unwrap();
+        case.board.make_move(col);
+        let won = case.board.was_won().is_some();
+        assert!(won || ai.negamax(&mut case.board, -1, 1) == -case.score.signum());
+    }
+}
+
+#[test]
+fn end_easy_analyze() {
+    let mut ai = AI::new();
+    for mut case in parse(END_EASY).into_iter().take(100) {
+        let score = ai.score(&mut case.board);
+        let evaluations = ai.analyze(&case.board);
+        let best = evaluations.iter().filter_map(Evaluation::score).max();
+        assert_eq!(best, Some(score));
+        for col in 0..COLS {
+            let full = !case.board.valid_moves().contains(&col);
+            assert_eq!(evaluations[col as usize] == Evaluation::Illegal, full);
+        }
+    }
+}
+
+#[test]
+fn end_easy_think() {
+    let mut ai = AI::new();
+    let limits = Limits { depth: None, time: None };
+    for mut case in parse(END_EASY).into_iter().take(100) {
+        let score = ai.score(&mut case.board);
+        let col = ai.think(&mut case.board, limits);
+        let evaluations = ai.analyze(&case.board);
+        assert_eq!(evaluations[col as usize].score().map(i8::signum), Some(score.signum()));
+    }
+}
+
+#[test]
+fn begin_hard_think() {
+    let mut ai = AI::new();
+    let limits = Limits { depth: Some(8), time: Some(Duration::from_millis(100)) };
+    for mut case in parse(BEGIN_HARD).into_iter().take(10) {
+        let col = ai.think(&mut case.board, limits);
+        assert!(case.board.safe_moves().contains(&col));
+    }
+}
+
+#[test]
+fn seeded_difficulty() {
+    let levels = [
+        Difficulty::Random,
+        Difficulty::Greedy,
+        Difficulty::Depth(4),
+        Difficulty::Mistakes(0.5),
+    ];
+    for &difficulty in &levels {
+        let mut games = Vec::new();
+        for _ in 0..2 {
+            let mut ai = AI::seeded(difficulty, 42);
+            let mut board = Board::new();
+            let mut moves = Vec::new();
+            while board.was_won().is_none() {
+                let col = match ai.solve(&mut board) {
+                    Some(col) => col,
+                    None => break,
+                };
+                assert!(board.valid_moves().contains(&col));
+                board.make_move(col);
+                moves.push(col);
+            }
+            games.push(moves);
+        }
+        assert_eq!(games[0], games[1]);
+    }
+}
+
+#[test]
+fn small_board_draw() {
+    let size = Size::new(4, 4).unwrap();
+    let mut ai = AI::with_size(size);
+    assert_eq!(ai.score(&mut Board::with_size(size)), 0);
+
+    let mut full = Board::parse(size, "1234123421433412").unwrap();
+    for &difficulty in &[Difficulty::Random, Difficulty::Greedy, Difficulty::Depth(4), Difficulty::Perfect] {
+        ai.set_difficulty(difficulty);
+        assert_eq!(ai.solve(&mut full), None);
+    }
+}
+
+#[test]
+fn wide_board_strong() {
+    let size = Size::new(9, 7).unwrap();
+    let mut ai = AI::with_size(size);
+    let mut board = Board::parse(size, "5566").unwrap();
+    assert_eq!(ai.score(&mut board), (size.cells() + 1 - 6) / 2);
+
+    let line = ai.principal_variation(&board);
+    assert_eq!(line.len(), 3);
+    for col in line { board.make_move(col); }
+    assert_eq!(board.was_won(), Some(WHITE));
+}
+
+#[test]
+fn tallest_board_strong() {
+    // 127 cells, where the bound on a win's score no longer fits an i8
+    let size = Size::new(1, 127).unwrap();
+    let mut ai = AI::with_budget(size, 0);
+    assert_eq!(ai.score(&mut Board::with_size(size)), 0);
+    assert_eq!(ai.analyze(&Board::with_size(size))[0].score(), Some(0));
+}
+
+#[test]
+fn saved_table() {
+    let path = env::temp_dir().join("connect-four-saved-table.bin");
+    let cases = parse(MIDDLE_EASY).into_iter().take(100).collect::<Vec<_>>();
+
+    let mut ai = AI::new();
+    for case in &cases {
+        ai.negamax(&mut case.board.clone(), -1, 1);
+    }
+    ai.save_table(&path).unwrap();
+
+    let mut ai = AI::new();
+    ai.load_table(&path).unwrap();
+    for case in &cases {
+        assert_eq!(ai.negamax(&mut case.board.clone(), -1, 1), case.score);
+    }
+
+    let mut small = AI::with_size(Size::new(4, 4).unwrap());
+    assert!(small.load_table(&path).is_err());
+
+    // Corrupt the entry count, which follows the magic, version and size
+    let mut bytes = fs::read(&path).unwrap();
+    for &entries in &[1u64 << 40, 1] {
+        bytes[10..18].copy_from_slice(&entries.to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+        let err = AI::new().load_table(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn small_table() {
+    let mut ai = AI::with_budget(Size::default(), 1 << 20);
+    for mut case in parse(MIDDLE_MEDIUM).into_iter().take(100) {
+        assert_eq!(ai.negamax(&mut case.board, -1, 1), case.score);
+        ai.clear_table();
+    }
+}
+
+#[test]
+fn table_budget() {
+    for &bytes in &[1000, 1 << 10, 1 << 20] {
+        assert!(AI::with_budget(Size::default(), bytes).table_bytes() <= bytes);
+    }
+    assert!(AI::min_table_bytes(Size::default()) <= 1000);
+
+    // Too many key bits for a table this small to keep positions apart
+    let size = Size::new(16, 7).unwrap();
+    let min = AI::min_table_bytes(size);
+    assert!(min > 1 << 10);
+    assert_eq!(AI::with_budget(size, 1 << 10).table_bytes(), min);
+}
+
+#[test]
+fn small_table_wide_board() {
+    let size = Size::new(8, 7).unwrap();
+    let mut small = AI::with_budget(size, 1 << 10);
+    let mut large = AI::with_size(size);
+    let mut seed = 12345u32;
+    for _ in 0..20 {
+        let mut board = Board::with_size(size);
+        while board.moves < 40 {
+            let safe = board.valid_moves().into_iter()
+                .filter(|&col| !board.will_win(col))
+                .collect::<Vec<_>>();
+            if safe.is_empty() { board.reset(); continue }
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            board.make_move(safe[(seed >> 16) as usize % safe.len()]);
+        }
+        assert_eq!(small.score(&mut board.clone()), large.score(&mut board.clone()));
+        small.clear_table();
+    }
+}
+
+#[test]
+fn small_table_widest_board() {
+    let path = env::temp_dir().join("connect-four-widest-table.bin");
+    let size = Size::new(16, 7).unwrap();
+    let ai = AI::with_budget(size, 1 << 10);
+    ai.save_table(&path).unwrap();
+    AI::with_size(size).load_table(&path).unwrap();
+
+    // Shrink the saved entry count, which follows the magic, version and size
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[10..18].copy_from_slice(&3u64.to_le_bytes());
+    fs::write(&path, bytes).unwrap();
+    assert!(AI::with_size(size).load_table(&path).is_err());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn generated_book() {
+    let size = Size::new(5, 4).unwrap();
+    let mut ai = AI::with_size(size);
+    let book = opening::generate(&mut ai, 4).unwrap();
+    assert_eq!(book.iter().filter(|(moves, _)| moves.len() == 1).count(), 3);
+
+    let text = book.iter()
+        .map(|&(ref moves, score)| format!("{} {}\n", moves, score))
+        .collect::<String>();
+    assert_eq!(opening::verify(&mut AI::with_size(size), &text), Ok(Vec::new()));
+
+    let mut wide = AI::with_size(Size::new(10, 4).unwrap());
+    assert!(opening::generate(&mut wide, 1).is_none());
+}
+
+#[test]
+fn binary_book() {
+    let text = include_str!("../src/opening.dat");
+    let lookup = opening::Lookup::new();
+    assert_eq!(lookup.depth(), 6);
+    for (moves, score) in opening::parse(text).unwrap() {
+        assert_eq!(lookup.get(Board::from(moves).canonical_key()), Some(score));
+    }
+    assert_eq!(lookup.get(Board::from("4444441").canonical_key()), None);
+
+    let bytes = opening::to_binary(text, Size::default()).unwrap();
+    assert!(opening::Lookup::from_bytes(bytes[..bytes.len() - 1].to_vec().into()).is_none());
+    assert!(opening::Lookup::from_bytes(bytes.into()).is_some());
+}
+
+#[test]
+fn malformed_book() {
+    use minimax::opening::BookError;
+    assert_eq!(opening::parse("1 0\n\n12\n"), Err(BookError::Line(3)));
+    assert_eq!(opening::parse("1 x\n"), Err(BookError::Line(1)));
+    assert_eq!(opening::parse("1 0 0\n"), Err(BookError::Line(1)));
+    assert_eq!(opening::parse("1 2\n"), Err(BookError::Line(1)));
+
+    // The standard book does not fit a smaller board
+    let size = Size::new(5, 4).unwrap();
+    let text = include_str!("../src/opening.dat");
+    assert!(matches!(opening::verify(&mut AI::with_size(size), text), Err(BookError::Moves(..))));
+    assert!(matches!(opening::to_binary("11111 0\n", size), Err(BookError::Moves(1, _))));
 }